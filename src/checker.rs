@@ -0,0 +1,1630 @@
+use crate::diagnostics::{line_col, Diagnostic};
+use crate::matching;
+use crate::resolver::{describe_function, original_name};
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+
+// Type checking.
+//
+// Scalars are ints, floats or bools; arrays, functions, structs and enums
+// are built from them, as below. Integer literals are ints, literals with a
+// decimal point are floats, and `true` and `false` are bools. Arithmetic on
+// two ints gives an int and anything involving a float gives a float, so
+// `7 / 2` is `3` while `7 / 2.0` is `3.5`. Integer `/` and `%` round towards
+// zero as they do in C, and `%` on floats is `fmod`. `^` always gives a
+// float.
+//
+// Comparisons give bools. `==` and `!=` compare two numbers or two bools, and
+// the others only numbers. `and`, `or`, `xor` and `!` only work on bools, and
+// the conditions of `if` and `while` have to be bools too, so a number is
+// tested by comparing it, as in `n != 0`. Bools are never used as numbers.
+//
+// A variable is an int if everything it is ever given is an int, a float if
+// it is also given floats, and a bool if it is given bools, which then have
+// to be all it is given. Assigning a float to a variable declared with an int
+// makes it a float rather than dropping the fraction. Loop counters follow
+// the same rule with the bounds and steps of their loop, so `0..1 step 0.25`
+// counts in floats, the variable of a `for ... in` loop over an array with
+// its elements, and parameters with the arguments of every call. A
+// function's return type follows it with the values it returns. A parameter
+// that is never given anything, as in a function that is never called, is
+// typed by how it is used instead: an array if it is indexed, the struct
+// with a field it reads if only one struct has it, and what its patterns
+// match. If that doesn't tell either, it has to be annotated.
+//
+// Parameters and return types can be written out, as in
+// `f(x: int, y: float) -> float`, and then they are fixed: ints can be given
+// where a float is expected, but giving or returning anything else of the
+// wrong type is an error.
+//
+// A function used as a value is typed by what it takes and returns, written
+// `fn(int, float) -> float`, and a call through it has to give it that: ints
+// can be given for floats, but nothing else is converted. Functions of
+// different types can't be given to the same variable, as they are called
+// differently. The parameters of a function used as a value are floats
+// unless they are written out or something else is given to them. Functions
+// can't be printed, compared or used in arithmetic.
+//
+// Arrays are typed by their elements, as in `[int]` or `[[float]]`. An array
+// variable's elements are widened by everything stored or pushed into them as
+// well as by the arrays it is given, so `a = [1, 2]` followed by `a[0] = 0.5`
+// makes `a` an array of floats. New arrays, made by a literal or `array(n)`,
+// become whatever array they are given to if their elements fit, so `[1, 2]`
+// is then an array of floats too, and `[]` can become any array. `array(n)`
+// fills itself with zeros, so it can't become an array of arrays or structs.
+// Other arrays are shared rather than copied, so their types have to match
+// exactly: an array of ints can't be given where an array of floats is
+// expected.
+// Indexes and lengths are ints, and arrays can't be compared.
+//
+// Structs are typed by their name. Each field of a struct is typed like a
+// variable, by what it is given when one is made and what is assigned to it,
+// unless its type is written in the declaration. A struct can't hold itself,
+// except through an array, and structs can't be compared.
+//
+// Enums are typed by their name too, and the fields of each variant are typed
+// like those of a struct. A `match` has to cover every value its patterns can
+// be given, not counting arms with a guard, which might be false, and every
+// arm has to match something the arms before it don't (see `matching`). A
+// name a pattern binds has the type of what it matches, and a `match`
+// expression has the type of its arms, widened like a variable.
+
+#[derive(Debug, Default)]
+pub struct Types {
+    // every variable, by its unique name
+    pub variables: HashMap<String, Type>,
+    // what every function returns, by its unique name
+    pub returns: HashMap<String, Type>,
+    // the parameters of every function, by its unique name
+    pub params: HashMap<String, Vec<String>>,
+    // new arrays that became another type of array, by where they start
+    pub arrays: HashMap<usize, Type>,
+    // the fields of every struct and every variant, in the order they are
+    // declared
+    pub structs: HashMap<String, Vec<(String, Type)>>,
+    // the variants of every enum, in the order they are declared
+    pub enums: HashMap<String, Vec<String>>,
+}
+
+impl Types {
+    pub fn variable(&self, name: &str) -> Type {
+        self.variables.get(name).cloned().unwrap_or(Type::Float)
+    }
+
+    pub fn returns(&self, name: &str) -> Type {
+        self.returns.get(name).cloned().unwrap_or(Type::Float)
+    }
+
+    // the type of the function `name` as a value
+    pub fn function(&self, name: &str) -> Type {
+        let params = self.params.get(name).into_iter().flatten();
+        Type::Function(
+            params.map(|param| self.variable(param)).collect(),
+            Box::new(self.returns(name)),
+        )
+    }
+
+    pub fn field(&self, name: &str, field: &str) -> Option<Type> {
+        self.structs
+            .get(name)?
+            .iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, ty)| ty.clone())
+    }
+
+    // the enum `variant` belongs to, if it is a variant rather than a struct
+    pub fn enum_of(&self, variant: &str) -> Option<&str> {
+        self.enums
+            .iter()
+            .find(|(_, variants)| variants.iter().any(|v| v == variant))
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+pub fn type_of(expr: &Expr, types: &Types) -> Type {
+    match &expr.kind {
+        ExprKind::Int(_) => Type::Int,
+        ExprKind::Float(_) => Type::Float,
+        ExprKind::Bool(_) => Type::Bool,
+        ExprKind::Str(_) => unreachable!("the resolver only allows strings in `print`"),
+        ExprKind::Ident(name) => types.variable(name),
+        ExprKind::Call { name, .. } => types.returns(name),
+        ExprKind::CallValue { callee, .. } => match type_of(callee, types) {
+            Type::Function(_, returns) => *returns,
+            _ => Type::Int,
+        },
+        ExprKind::Function(name) | ExprKind::Lambda { name, .. } => types.function(name),
+        ExprKind::Pow(..) => Type::Float,
+        ExprKind::Add(lhs, rhs)
+        | ExprKind::Sub(lhs, rhs)
+        | ExprKind::Mul(lhs, rhs)
+        | ExprKind::Div(lhs, rhs)
+        | ExprKind::Mod(lhs, rhs) => type_of(lhs, types).join(type_of(rhs, types)),
+        ExprKind::Leq(..)
+        | ExprKind::Geq(..)
+        | ExprKind::Lt(..)
+        | ExprKind::Gt(..)
+        | ExprKind::Eq(..)
+        | ExprKind::Neq(..)
+        | ExprKind::And(..)
+        | ExprKind::Or(..)
+        | ExprKind::Xor(..)
+        | ExprKind::Not(_) => Type::Bool,
+        ExprKind::Neg(e) | ExprKind::Pos(e) => type_of(e, types),
+        ExprKind::Array(_) | ExprKind::NewArray { .. }
+            if types.arrays.contains_key(&expr.span.start) =>
+        {
+            types.arrays[&expr.span.start].clone()
+        }
+        ExprKind::Array(elements) => Type::Array(Box::new(
+            elements
+                .iter()
+                .map(|e| type_of(e, types))
+                .reduce(widen)
+                .unwrap_or(Type::Int),
+        )),
+        ExprKind::NewArray { fill, .. } => Type::Array(Box::new(
+            fill.as_ref().map_or(Type::Int, |fill| type_of(fill, types)),
+        )),
+        ExprKind::Index { array, .. } => match type_of(array, types) {
+            Type::Array(element) => *element,
+            _ => Type::Int,
+        },
+        ExprKind::Len(_) => Type::Int,
+        ExprKind::Struct { name, .. } => Type::Struct(name.clone()),
+        ExprKind::Field { expr, name } => match type_of(expr, types) {
+            Type::Struct(s) => types.field(&s, name).unwrap_or(Type::Int),
+            _ => Type::Int,
+        },
+        ExprKind::Variant { ty, .. } => Type::Enum(ty.clone()),
+        ExprKind::Match { arms, .. } => arms
+            .iter()
+            .map(|arm| type_of(&arm.body, types))
+            .reduce(widen)
+            .unwrap_or(Type::Int),
+        ExprKind::Record(_) => unreachable!("the resolver makes records into structs"),
+    }
+}
+
+// an array `depth` arrays deep of `ty`s
+fn wrap(depth: usize, ty: Type) -> Type {
+    (0..depth).fold(ty, |ty, _| Type::Array(Box::new(ty)))
+}
+
+// the variable an element is stored in, and how many arrays deep it is, as
+// in `grid` and 2 for `grid[i][j] = x`
+fn place(expr: &Expr) -> Option<(&str, usize)> {
+    match &expr.kind {
+        ExprKind::Ident(name) => Some((name, 1)),
+        ExprKind::Index { array, .. } => place(array).map(|(name, depth)| (name, depth + 1)),
+        _ => None,
+    }
+}
+
+// what inference settles on for something given both types: a bool wins, so
+// that everything given a bool is a bool and the rest of what it is given is
+// reported as wrong, and arrays and then structs, enums and functions win
+// over both. Two functions taking as many parameters widen what they take
+// and return, as their own types are still being inferred.
+fn widen(a: Type, b: Type) -> Type {
+    match (a, b) {
+        (Type::Array(a), Type::Array(b)) => Type::Array(Box::new(widen(*a, *b))),
+        (Type::Array(a), _) | (_, Type::Array(a)) => Type::Array(a),
+        (Type::Struct(a), _) | (_, Type::Struct(a)) => Type::Struct(a),
+        (Type::Enum(a), _) | (_, Type::Enum(a)) => Type::Enum(a),
+        (Type::Function(a, r), Type::Function(b, s)) if a.len() == b.len() => Type::Function(
+            a.into_iter().zip(b).map(|(a, b)| widen(a, b)).collect(),
+            Box::new(widen(*r, *s)),
+        ),
+        (f @ Type::Function(..), _) | (_, f @ Type::Function(..)) => f,
+        (Type::Bool, _) | (_, Type::Bool) => Type::Bool,
+        (a, b) => a.join(b),
+    }
+}
+
+fn a(ty: &Type) -> String {
+    match ty {
+        Type::Int => String::from("an int"),
+        Type::Float => String::from("a float"),
+        Type::Bool => String::from("a bool"),
+        Type::Array(element) => format!("an array of {}", plural(element)),
+        Type::Struct(name) | Type::Enum(name) if name.starts_with(['A', 'E', 'I', 'O', 'U']) => {
+            format!("an {}", name)
+        }
+        Type::Struct(name) | Type::Enum(name) => format!("a {}", name),
+        Type::Function(..) => format!("a `{}`", ty),
+    }
+}
+
+fn plural(ty: &Type) -> String {
+    match ty {
+        Type::Int => String::from("ints"),
+        Type::Float => String::from("floats"),
+        Type::Bool => String::from("bools"),
+        Type::Array(element) => format!("arrays of {}", plural(element)),
+        Type::Struct(name) | Type::Enum(name) => format!("{} values", name),
+        Type::Function(..) => format!("`{}` functions", ty),
+    }
+}
+
+struct Function<'a> {
+    params: &'a [Param],
+    returns: Option<&'a Annotation>,
+    // every value it returns
+    values: Vec<&'a Expr>,
+}
+
+struct Struct<'a> {
+    fields: &'a [Param],
+    span: Span,
+}
+
+// how a variable is taken apart: as an array so many arrays deep, by a field,
+// or by a pattern
+enum Use<'a> {
+    Array(usize),
+    Field(&'a str),
+    Pattern(&'a Pattern),
+}
+
+pub struct Checker<'a> {
+    source: &'a str,
+    functions: HashMap<&'a str, Function<'a>>,
+    // every value given to each variable
+    values: HashMap<&'a str, Vec<&'a Expr>>,
+    // the arrays each `for ... in` loop goes through, by its variable
+    iterated: HashMap<&'a str, Vec<&'a Expr>>,
+    // every value stored or pushed into each array variable, and how deep
+    elements: HashMap<&'a str, Vec<(usize, &'a Expr)>>,
+    // the types new arrays become, see `Types::arrays`
+    arrays: HashMap<usize, Type>,
+    annotations: HashMap<&'a str, &'a Annotation>,
+    // every struct's fields, what each field is given when one is made, and
+    // the field assignments, whose structs are only known once inferred
+    structs: HashMap<&'a str, Struct<'a>>,
+    fields: HashMap<(&'a str, &'a str), Vec<&'a Expr>>,
+    assigned: Vec<(&'a Expr, &'a str, &'a Expr)>,
+    // every enum's variants, which are in `structs` along with their fields
+    enums: HashMap<&'a str, Vec<&'a str>>,
+    // names bound inside a pattern, by the struct or variant and the index of
+    // the field they stand for
+    bound: HashMap<&'a str, (&'a str, usize)>,
+    calls: Vec<(&'a str, &'a [Expr])>,
+    // how each variable is used, which is all there is to go on for one that
+    // is never given anything, like a parameter of a function never called
+    uses: HashMap<&'a str, Vec<Use<'a>>>,
+    // functions used as values, whose parameters are floats unless they are
+    // given something else
+    escaping: HashSet<&'a str>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    pub fn check(prog: &'a Program, source: &'a str) -> Result<Types, Vec<Diagnostic>> {
+        let mut checker = Checker {
+            source,
+            functions: HashMap::new(),
+            values: HashMap::new(),
+            iterated: HashMap::new(),
+            elements: HashMap::new(),
+            arrays: HashMap::new(),
+            annotations: HashMap::new(),
+            structs: HashMap::new(),
+            fields: HashMap::new(),
+            assigned: Vec::new(),
+            enums: HashMap::new(),
+            bound: HashMap::new(),
+            calls: Vec::new(),
+            uses: HashMap::new(),
+            escaping: HashSet::new(),
+            errors: Vec::new(),
+        };
+        checker.collect(prog, None);
+        for (name, args) in std::mem::take(&mut checker.calls) {
+            let params = checker.functions[name].params;
+            for (param, arg) in params.iter().zip(args) {
+                checker.give(&param.name, arg);
+            }
+        }
+
+        let mut types = checker.infer();
+        checker.check_values(&types);
+        // the arrays given to variables and returned are settled before the
+        // rest is checked, which only settles the arrays left
+        types.arrays = std::mem::take(&mut checker.arrays);
+        checker.check_structs(&types);
+        checker.check_body(prog, false, &types);
+        for (start, ty) in std::mem::take(&mut checker.arrays) {
+            types.arrays.entry(start).or_insert(ty);
+        }
+
+        if checker.errors.is_empty() {
+            Ok(types)
+        } else {
+            checker.errors.sort_by_key(|e| e.span.start);
+            Err(checker.errors)
+        }
+    }
+
+    // everything starts as an int and is widened by what it is found to be
+    // given, until nothing changes
+    fn infer(&self) -> Types {
+        let mut types = Types::default();
+        for &name in self.values.keys() {
+            let ty = match self.annotations.get(name) {
+                Some(annotation) => annotation.ty.clone(),
+                None if self.ungiven(name) => self.used_as(name).unwrap_or(Type::Int),
+                None => Type::Int,
+            };
+            types.variables.insert(String::from(name), ty);
+        }
+        for (&name, f) in &self.functions {
+            if self.escaping.contains(name) {
+                for param in f.params.iter().filter(|param| param.ty.is_none()) {
+                    let ty = match self.ungiven(&param.name) {
+                        true => self.used_as(&param.name),
+                        false => None,
+                    };
+                    types
+                        .variables
+                        .insert(param.name.clone(), ty.unwrap_or(Type::Float));
+                }
+            }
+            let params = f.params.iter().map(|param| param.name.clone());
+            types.params.insert(String::from(name), params.collect());
+            let ty = f
+                .returns
+                .map_or(Type::Int, |annotation| annotation.ty.clone());
+            types.returns.insert(String::from(name), ty);
+        }
+        for (&name, s) in &self.structs {
+            let fields = s.fields.iter().map(|field| {
+                let ty = field.ty.as_ref().map_or(Type::Int, |a| a.ty.clone());
+                (field.name.clone(), ty)
+            });
+            types.structs.insert(String::from(name), fields.collect());
+        }
+        for (&name, variants) in &self.enums {
+            let variants = variants.iter().map(|&v| String::from(v));
+            types.enums.insert(String::from(name), variants.collect());
+        }
+
+        loop {
+            let mut changed = false;
+            for (&name, values) in &self.values {
+                if self.annotations.contains_key(name) {
+                    continue;
+                }
+                let ty = values.iter().fold(types.variables[name].clone(), |ty, v| {
+                    widen(ty, type_of(v, &types))
+                });
+                let ty = self
+                    .elements
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .fold(ty, |ty, &(depth, v)| {
+                        widen(ty, wrap(depth, type_of(v, &types)))
+                    });
+                let ty = self
+                    .iterated
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|array| match type_of(array, &types) {
+                        Type::Array(element) => Some(*element),
+                        _ => None,
+                    })
+                    .fold(ty, widen);
+                let ty = match self.bound.get(name) {
+                    Some(&(ctor, i)) => widen(ty, types.structs[ctor][i].1.clone()),
+                    None => ty,
+                };
+                if ty != types.variables[name] {
+                    types.variables.insert(String::from(name), ty);
+                    changed = true;
+                }
+            }
+            for (&name, f) in &self.functions {
+                if f.returns.is_some() {
+                    continue;
+                }
+                let ty = f.values.iter().fold(types.returns[name].clone(), |ty, v| {
+                    widen(ty, type_of(v, &types))
+                });
+                if ty != types.returns[name] {
+                    types.returns.insert(String::from(name), ty);
+                    changed = true;
+                }
+            }
+            for (&name, s) in &self.structs {
+                for (i, field) in s.fields.iter().enumerate() {
+                    if field.ty.is_some() {
+                        continue;
+                    }
+                    let values = self.field_values(name, &field.name, &types);
+                    let before = &types.structs[name][i].1;
+                    let ty = values
+                        .iter()
+                        .fold(before.clone(), |ty, v| widen(ty, type_of(v, &types)));
+                    if ty != *before {
+                        types.structs.get_mut(name).unwrap()[i].1 = ty;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return types;
+            }
+        }
+    }
+
+    // whether nothing is ever given to the variable `name`, so its type can
+    // only come from how it is used
+    fn ungiven(&self, name: &str) -> bool {
+        self.values.get(name).is_some_and(Vec::is_empty)
+            && !self.annotations.contains_key(name)
+            && !self.bound.contains_key(name)
+            && !self.elements.contains_key(name)
+            && !self.iterated.contains_key(name)
+    }
+
+    // what the variable `name` is used as: an array if it is indexed, the
+    // struct with a field it reads if only one has it, and whatever its
+    // patterns match, if any of that tells
+    fn used_as(&self, name: &str) -> Option<Type> {
+        let variants = self.enums.values().flatten().collect::<Vec<_>>();
+        let used = self.uses.get(name).into_iter().flatten();
+        used.filter_map(|used| match used {
+            Use::Array(depth) => Some(wrap(*depth, Type::Int)),
+            Use::Field(field) => {
+                let mut owners = self.structs.iter().filter(|(name, s)| {
+                    !variants.contains(name) && s.fields.iter().any(|f| f.name == *field)
+                });
+                match (owners.next(), owners.next()) {
+                    (Some((&owner, _)), None) => Some(Type::Struct(String::from(owner))),
+                    _ => None,
+                }
+            }
+            Use::Pattern(pattern) => match &pattern.kind {
+                PatternKind::Bool(_) => Some(Type::Bool),
+                PatternKind::Constructor { name, .. } => {
+                    let owner = self
+                        .enums
+                        .iter()
+                        .find(|(_, vs)| vs.contains(&name.as_str()));
+                    Some(match owner {
+                        Some((&e, _)) => Type::Enum(String::from(e)),
+                        None => Type::Struct(name.clone()),
+                    })
+                }
+                _ => None,
+            },
+        })
+        .reduce(widen)
+    }
+
+    // the error for taking apart a variable that is never given anything in
+    // a way that doesn't tell what it is
+    fn uninferred(&self, expr: &Expr) -> Option<Diagnostic> {
+        match &expr.kind {
+            ExprKind::Ident(name) if self.ungiven(name) => Some(
+                Diagnostic::new(
+                    format!(
+                        "cannot infer the type of `{}`, annotate it",
+                        original_name(name)
+                    ),
+                    expr.span,
+                )
+                .with_note(format!(
+                    "{} is never given anything, so its type can only come from how it is used",
+                    original_name(name)
+                )),
+            ),
+            _ => None,
+        }
+    }
+
+    // everything given to a variable or returned from a function has to fit
+    // its type
+    fn check_values(&mut self, types: &Types) {
+        let mut errors = Vec::new();
+
+        let values = std::mem::take(&mut self.values);
+        for (&name, values) in &values {
+            let ty = types.variable(name);
+            let why = match self.annotations.get(name) {
+                Some(annotation) => format!(
+                    "{} is declared as {} at {}",
+                    original_name(name),
+                    a(&ty),
+                    self.location(annotation.span)
+                ),
+                None => {
+                    // the first element stored in it that made it what it is
+                    let stored = self.elements.get(name).and_then(|elements| {
+                        elements
+                            .iter()
+                            .filter(|&&(depth, value)| wrap(depth, type_of(value, types)) == ty)
+                            .map(|(_, value)| value.span)
+                            .min_by_key(|s| s.start)
+                    });
+                    match stored {
+                        Some(span) if !values.iter().any(|v| type_of(v, types) == ty) => format!(
+                            "{} has an element stored in it at {}",
+                            original_name(name),
+                            self.location(span)
+                        ),
+                        _ => self.reason(original_name(name), &ty, values, types),
+                    }
+                }
+            };
+            for value in values {
+                let what = format!("{} is {}", original_name(name), a(&ty));
+                if let Some(error) = self.misfit(&ty, value, types, &what) {
+                    errors.push(error.with_note(why.clone()));
+                }
+            }
+        }
+
+        self.values = values;
+
+        for (&name, arrays) in &self.iterated {
+            let ty = types.variable(name);
+            for array in arrays {
+                match type_of(array, types) {
+                    Type::Array(element) if !ty.accepts(&element) => errors.push(Diagnostic::new(
+                        format!(
+                            "{} is {}, but this array holds {}",
+                            original_name(name),
+                            a(&ty),
+                            plural(&element)
+                        ),
+                        array.span,
+                    )),
+                    _ => {}
+                }
+            }
+        }
+
+        let functions = std::mem::take(&mut self.functions);
+        for (&name, f) in &functions {
+            let ty = types.returns(name);
+            let why = match f.returns {
+                Some(annotation) => format!(
+                    "the return type is declared at {}",
+                    self.location(annotation.span)
+                ),
+                None => self.reason(&describe_function(name), &ty, &f.values, types),
+            };
+            for value in &f.values {
+                let what = format!("{} returns {}", describe_function(name), a(&ty));
+                if let Some(error) = self.misfit(&ty, value, types, &what) {
+                    errors.push(error.with_note(why.clone()));
+                }
+            }
+        }
+        self.functions = functions;
+
+        let structs = std::mem::take(&mut self.structs);
+        for (&name, s) in &structs {
+            for field in s.fields {
+                let what = format!("{}.{}", name, field.name);
+                let ty = types.field(name, &field.name).unwrap();
+                let values = self.field_values(name, &field.name, types);
+                let why = match &field.ty {
+                    Some(annotation) => format!(
+                        "{} is declared as {} at {}",
+                        what,
+                        a(&ty),
+                        self.location(annotation.span)
+                    ),
+                    None => self.reason(&what, &ty, &values, types),
+                };
+                for value in values {
+                    let what = format!("{} is {}", what, a(&ty));
+                    if let Some(error) = self.misfit(&ty, value, types, &what) {
+                        errors.push(error.with_note(why.clone()));
+                    }
+                }
+            }
+        }
+        self.structs = structs;
+
+        self.errors.extend(errors);
+    }
+
+    // everything given to a field, when a struct is made or by assignment
+    fn field_values(&self, name: &str, field: &str, types: &Types) -> Vec<&'a Expr> {
+        let assigned = self.assigned.iter().filter(|(target, f, _)| {
+            *f == field && type_of(target, types) == Type::Struct(String::from(name))
+        });
+        let made = self.fields.get(&(name, field)).into_iter().flatten();
+        made.copied()
+            .chain(assigned.map(|&(_, _, value)| value))
+            .collect()
+    }
+
+    // a struct or an enum that holds itself would never end
+    fn check_structs(&mut self, types: &Types) {
+        let variants = self.enums.values().flatten().copied().collect::<Vec<_>>();
+        let structs = self.structs.keys().filter(|name| !variants.contains(name));
+        let enums = self.enums.keys().map(|name| (name, "enum", "an enum"));
+        let mut errors = Vec::new();
+
+        for (&name, kind, article) in structs
+            .map(|name| (name, "struct", "a struct"))
+            .chain(enums)
+        {
+            let mut path = Vec::new();
+            if !holds(name, name, types, &mut Vec::new(), &mut path) {
+                continue;
+            }
+            // the first field on the way, as `Cons.tail` in an enum
+            let (owner, first) = match self.enums.get(name) {
+                Some(_) => path[0].split_once('.').unwrap(),
+                None => (name, path[0].as_str()),
+            };
+            let s = &self.structs[owner];
+            let span = s
+                .fields
+                .iter()
+                .find(|field| field.name == first)
+                .and_then(|field| field.ty.as_ref())
+                .map_or(s.span, |annotation| annotation.span);
+            let through = match kind {
+                "struct" => format!("through {}.{}", name, path.join(".")),
+                _ => format!("through {}", path.join(".")),
+            };
+            errors.push(
+                Diagnostic::new(format!("{} {} holds itself", kind, name), span)
+                    .with_label(through)
+                    .with_note(format!("{} can hold an array of itself instead", article)),
+            );
+        }
+
+        self.errors.extend(errors);
+    }
+
+    // the patterns of a `match` on `value` have to fit what it matches, and
+    // together cover all of it
+    fn check_match<T>(&mut self, value: &Expr, arms: &[Arm<T>], span: Span, types: &Types) {
+        let ty = type_of(value, types);
+        let mut fits = true;
+        for arm in arms {
+            fits &= self.pattern(&arm.pattern, &ty, true, types);
+            if let Some(guard) = &arm.guard {
+                self.condition(guard, "a guard", types);
+            }
+        }
+        if !fits {
+            return;
+        }
+
+        let patterns = arms
+            .iter()
+            .map(|arm| (&arm.pattern, arm.guard.is_some()))
+            .collect::<Vec<_>>();
+        for i in matching::redundant(&ty, &patterns, types) {
+            self.errors.push(
+                Diagnostic::new("this arm can never match", arms[i].pattern.span)
+                    .with_label("the arms before it match everything it does"),
+            );
+        }
+        if let Some(missing) = matching::uncovered(&ty, &patterns, types) {
+            let mut error = Diagnostic::new("this `match` doesn't cover every case", span)
+                .with_label(format!("nothing matches `{}`", missing));
+            if arms.iter().any(|arm| arm.guard.is_some()) {
+                error = error.with_note("arms with a guard don't count, as it might be false");
+            }
+            self.errors.push(error);
+        }
+    }
+
+    // whether `pattern` can match a `ty`; the names it binds at the top take
+    // the type of the value matched like a variable, and the ones inside it
+    // have to hold their field
+    fn pattern(&mut self, pattern: &Pattern, ty: &Type, top: bool, types: &Types) -> bool {
+        let found = match &pattern.kind {
+            PatternKind::Wildcard => return true,
+            PatternKind::Binding(name) => {
+                let variable = types.variable(name);
+                if top || variable.accepts(ty) {
+                    return true;
+                }
+                self.errors.push(Diagnostic::new(
+                    format!(
+                        "{} is {}, but the field it binds is {}",
+                        original_name(name),
+                        a(&variable),
+                        a(ty)
+                    ),
+                    pattern.span,
+                ));
+                return false;
+            }
+            PatternKind::Int(_) => Type::Int,
+            PatternKind::Bool(_) => Type::Bool,
+            PatternKind::Constructor { name, .. } => match types.enum_of(name) {
+                Some(e) => Type::Enum(String::from(e)),
+                None => Type::Struct(name.clone()),
+            },
+        };
+        if found != *ty {
+            self.errors.push(Diagnostic::new(
+                format!(
+                    "this pattern is {}, but the value it matches is {}",
+                    a(&found),
+                    a(ty)
+                ),
+                pattern.span,
+            ));
+            return false;
+        }
+
+        let mut fits = true;
+        if let PatternKind::Constructor { name, fields } = &pattern.kind {
+            for (field, (_, ty)) in fields.iter().zip(&types.structs[name]) {
+                fits &= self.pattern(field, ty, false, types);
+            }
+        }
+        fits
+    }
+
+    // whether `value` can be given where `ty` is expected, which a new array
+    // can if its elements can, becoming a `ty`
+    fn fits(&mut self, ty: &Type, value: &Expr, types: &Types) -> bool {
+        let fits = match (&value.kind, ty) {
+            (ExprKind::Array(elements), Type::Array(element)) => {
+                elements.iter().all(|e| self.fits(element, e, types))
+            }
+            (
+                ExprKind::NewArray {
+                    fill: Some(fill), ..
+                },
+                Type::Array(element),
+            ) => self.fits(element, fill, types),
+            (ExprKind::NewArray { fill: None, .. }, Type::Array(element)) => {
+                element.is_number() || **element == Type::Bool
+            }
+            _ => return ty.accepts(&type_of(value, types)),
+        };
+        if fits {
+            self.arrays
+                .entry(value.span.start)
+                .or_insert_with(|| ty.clone());
+        }
+        fits
+    }
+
+    // the error for `value` not fitting a `ty`, which isn't reported for a new
+    // array that is a `ty` itself, as one of its elements is what doesn't fit
+    fn misfit(&mut self, ty: &Type, value: &Expr, types: &Types, what: &str) -> Option<Diagnostic> {
+        let found = type_of(value, types);
+        if self.fits(ty, value, types) || found == *ty {
+            return None;
+        }
+        let error = Diagnostic::new(format!("{}, but this is {}", what, a(&found)), value.span);
+        Some(match (&value.kind, ty) {
+            (ExprKind::NewArray { fill: None, .. }, Type::Array(element))
+                if matches!(**element, Type::Array(_) | Type::Struct(_) | Type::Enum(_)) =>
+            {
+                error.with_note(format!("`array(n)` fills itself with zeros, so it can't make an array of {}; start with `[]` and push to it instead", plural(element)))
+            }
+            _ => error,
+        })
+    }
+
+    // why something inferred to be `ty` is one: the first value of that type
+    fn reason(&self, what: &str, ty: &Type, values: &[&Expr], types: &Types) -> String {
+        let first = values
+            .iter()
+            .filter(|v| type_of(v, types) == *ty)
+            .min_by_key(|v| v.span.start);
+        match first {
+            Some(value) => format!(
+                "{} is given {} at {}",
+                what,
+                a(ty),
+                self.location(value.span)
+            ),
+            None => format!("{} is {}", what, a(ty)),
+        }
+    }
+
+    // `tail` is whether `body` finishes a function, whose last expression is
+    // returned rather than printed
+    fn check_body(&mut self, body: &'a Program, tail: bool, types: &Types) {
+        for (i, stmt) in body.iter().enumerate() {
+            let tail = tail && i + 1 == body.len();
+            match &stmt.kind {
+                StmtKind::Expression(expr) if !tail => {
+                    self.format(expr, Format::default(), types);
+                }
+                StmtKind::IfStatement { cond, .. } => self.condition(cond, "an `if`", types),
+                StmtKind::While { expr, .. } => self.condition(expr, "a `while` loop", types),
+                StmtKind::For { exprs, .. } => {
+                    for expr in exprs {
+                        self.number(expr, "a `for` loop", types);
+                    }
+                }
+                StmtKind::ForRange { .. } => {
+                    for expr in stmt.exprs() {
+                        self.number(expr, "a range", types);
+                    }
+                }
+                StmtKind::ForEach { array, .. } => {
+                    self.array(array, "`for ... in`", types);
+                }
+                StmtKind::Print(args) => {
+                    for (arg, format) in args {
+                        self.format(arg, *format, types);
+                    }
+                }
+                StmtKind::IndexAssignment {
+                    array,
+                    index,
+                    value,
+                } => {
+                    if let Some(element) = self.array(array, "indexing", types) {
+                        self.store(&element, value, types);
+                    }
+                    self.int(index, "an array index", types);
+                }
+                StmtKind::Push { array, value } => {
+                    if let Some(element) = self.array(array, "`push`", types) {
+                        self.store(&element, value, types);
+                    }
+                }
+                StmtKind::FieldAssignment { target, field, .. } => {
+                    self.field(target, field, stmt.span, types);
+                }
+                StmtKind::Match { value, arms } => self.check_match(value, arms, stmt.span, types),
+                _ => {}
+            }
+            for expr in stmt.exprs() {
+                self.check_expr(expr, types);
+            }
+            let tail = match &stmt.kind {
+                StmtKind::FunctionDefinition { .. } => true,
+                StmtKind::IfStatement {
+                    branch: Some(_), ..
+                }
+                | StmtKind::Match { .. } => tail,
+                _ => false,
+            };
+            for block in stmt.blocks() {
+                self.check_body(block, tail, types);
+            }
+        }
+    }
+
+    fn check_expr(&mut self, expr: &'a Expr, types: &Types) {
+        match &expr.kind {
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mul(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::Leq(lhs, rhs)
+            | ExprKind::Geq(lhs, rhs)
+            | ExprKind::Lt(lhs, rhs)
+            | ExprKind::Gt(lhs, rhs) => {
+                let op = format!("`{}`", operator(&expr.kind));
+                self.number(lhs, &op, types);
+                self.number(rhs, &op, types);
+            }
+            ExprKind::Neg(e) | ExprKind::Pos(e) => {
+                self.number(e, &format!("`{}`", operator(&expr.kind)), types)
+            }
+            ExprKind::And(lhs, rhs) | ExprKind::Or(lhs, rhs) | ExprKind::Xor(lhs, rhs) => {
+                let op = format!("`{}`", operator(&expr.kind));
+                self.condition(lhs, &op, types);
+                self.condition(rhs, &op, types);
+            }
+            ExprKind::Not(e) => self.condition(e, "`!`", types),
+            ExprKind::Eq(lhs, rhs) | ExprKind::Neq(lhs, rhs) => {
+                let (l, r) = (type_of(lhs, types), type_of(rhs, types));
+                let comparable = (l.is_number() && r.is_number()) || l == Type::Bool && r == l;
+                if !comparable {
+                    self.errors.push(
+                        Diagnostic::new(
+                            format!(
+                                "`{}` compares {} with {}",
+                                operator(&expr.kind),
+                                a(&l),
+                                a(&r)
+                            ),
+                            expr.span,
+                        )
+                        .with_label("both sides need to be numbers, or both bools"),
+                    );
+                }
+            }
+            ExprKind::CallValue { callee, args } => self.call_value(callee, args, expr.span, types),
+            ExprKind::Lambda { body, .. } => self.check_body(body, true, types),
+            ExprKind::Str(segments) => {
+                for segment in segments {
+                    if let Segment::Value(value, format) = segment {
+                        self.format(value, *format, types);
+                    }
+                }
+            }
+            ExprKind::Array(elements) => {
+                if let Type::Array(element) = type_of(expr, types) {
+                    for e in elements {
+                        self.store(&element, e, types);
+                    }
+                }
+            }
+            ExprKind::NewArray { len, .. } => self.int(len, "an array length", types),
+            ExprKind::Index { array, index } => {
+                self.array(array, "indexing", types);
+                self.int(index, "an array index", types);
+            }
+            ExprKind::Len(array) => {
+                self.array(array, "`len`", types);
+            }
+            ExprKind::Field { expr: e, name } => {
+                self.field(e, name, expr.span, types);
+            }
+            ExprKind::Match { value, arms } => {
+                self.check_match(value, arms, expr.span, types);
+                let ty = type_of(expr, types);
+                let what = format!("this `match` gives {}", plural(&ty));
+                for arm in arms {
+                    if let Some(error) = self.misfit(&ty, &arm.body, types, &what) {
+                        self.errors.push(error);
+                    }
+                }
+            }
+            ExprKind::Record(_) => unreachable!("the resolver makes records into structs"),
+            ExprKind::Int(_)
+            | ExprKind::Struct { .. }
+            | ExprKind::Variant { .. }
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Ident(_)
+            | ExprKind::Call { .. }
+            | ExprKind::Function(_) => {}
+        }
+        for child in expr.children() {
+            self.check_expr(child, types);
+        }
+    }
+
+    // a call through a function value, at `span`, has to give it what it
+    // takes
+    fn call_value(&mut self, callee: &Expr, args: &[Expr], span: Span, types: &Types) {
+        let ty = type_of(callee, types);
+        let Type::Function(params, _) = &ty else {
+            let error = self.uninferred(callee).unwrap_or_else(|| {
+                Diagnostic::new(
+                    format!("only functions can be called, but this is {}", a(&ty)),
+                    callee.span,
+                )
+            });
+            self.errors.push(error);
+            return;
+        };
+        let called = &self.source[callee.span.start..callee.span.end];
+        if params.len() != args.len() {
+            self.errors.push(
+                Diagnostic::new(
+                    format!(
+                        "`{}` takes {} argument{} but {} {} given",
+                        called,
+                        params.len(),
+                        if params.len() == 1 { "" } else { "s" },
+                        args.len(),
+                        if args.len() == 1 { "was" } else { "were" }
+                    ),
+                    span,
+                )
+                .with_note(format!("`{}` is {}", called, a(&ty))),
+            );
+            return;
+        }
+        for (param, arg) in params.iter().zip(args) {
+            let what = format!("`{}` takes {} for this argument", called, a(param));
+            if let Some(error) = self.misfit(param, arg, types, &what) {
+                self.errors.push(error);
+            }
+        }
+    }
+
+    fn number(&mut self, expr: &Expr, context: &str, types: &Types) {
+        let ty = type_of(expr, types);
+        if !ty.is_number() {
+            self.errors.push(Diagnostic::new(
+                format!("{} needs a number, but this is {}", context, a(&ty)),
+                expr.span,
+            ));
+        }
+    }
+
+    fn int(&mut self, expr: &Expr, context: &str, types: &Types) {
+        let ty = type_of(expr, types);
+        if ty != Type::Int {
+            self.errors.push(Diagnostic::new(
+                format!("{} needs an int, but this is {}", context, a(&ty)),
+                expr.span,
+            ));
+        }
+    }
+
+    // the type of the elements of `expr`, if it is an array
+    fn array(&mut self, expr: &Expr, context: &str, types: &Types) -> Option<Type> {
+        match type_of(expr, types) {
+            Type::Array(element) => Some(*element),
+            ty => {
+                let error = self.uninferred(expr).unwrap_or_else(|| {
+                    Diagnostic::new(
+                        format!("{} needs an array, but this is {}", context, a(&ty)),
+                        expr.span,
+                    )
+                });
+                self.errors.push(error);
+                None
+            }
+        }
+    }
+
+    // `expr` has to be a struct with a field `name`, used at `span`
+    fn field(&mut self, expr: &Expr, name: &str, span: Span, types: &Types) {
+        let error = match type_of(expr, types) {
+            Type::Struct(s) => match types.field(&s, name) {
+                Some(_) => return,
+                None => {
+                    let fields = types.structs[&s]
+                        .iter()
+                        .map(|(f, _)| f.as_str())
+                        .collect::<Vec<_>>();
+                    Diagnostic::new(format!("{} has no field {}", s, name), span)
+                        .with_note(format!("its fields are {}", fields.join(", ")))
+                }
+            },
+            ty => self.uninferred(expr).unwrap_or_else(|| {
+                Diagnostic::new(
+                    format!("`.{}` needs a struct, but this is {}", name, a(&ty)),
+                    expr.span,
+                )
+            }),
+        };
+        self.errors.push(error);
+    }
+
+    // `value` put into an array of `element`s
+    fn store(&mut self, element: &Type, value: &Expr, types: &Types) {
+        let what = format!("this array holds {}", plural(element));
+        if let Some(error) = self.misfit(element, value, types, &what) {
+            self.errors.push(error);
+        }
+    }
+
+    fn condition(&mut self, expr: &Expr, context: &str, types: &Types) {
+        let ty = type_of(expr, types);
+        if ty != Type::Bool {
+            let error = Diagnostic::new(
+                format!("{} needs a bool, but this is {}", context, a(&ty)),
+                expr.span,
+            );
+            self.errors.push(if ty.is_number() {
+                error.with_note("compare it to get a bool, as in `n != 0`")
+            } else {
+                error
+            });
+        }
+    }
+
+    // bools print as `true` or `false`, which can only be padded, and arrays
+    // and structs print what they hold as it is
+    fn format(&mut self, expr: &Expr, format: Format, types: &Types) {
+        if matches!(expr.kind, ExprKind::Str(_)) {
+            return;
+        }
+        match type_of(expr, types) {
+            Type::Bool if format.precision.is_some() || format.scientific => {
+                self.errors.push(
+                    Diagnostic::new("bools can only be given a width", expr.span)
+                        .with_label("precision and `e` are for numbers"),
+                );
+            }
+            Type::Array(_) if format != Format::default() => {
+                self.errors.push(
+                    Diagnostic::new("arrays can't be given a format", expr.span)
+                        .with_label("print their elements to format them"),
+                );
+            }
+            Type::Struct(_) if format != Format::default() => {
+                self.errors.push(
+                    Diagnostic::new("structs can't be given a format", expr.span)
+                        .with_label("print their fields to format them"),
+                );
+            }
+            Type::Enum(_) if format != Format::default() => {
+                self.errors.push(
+                    Diagnostic::new("enums can't be given a format", expr.span)
+                        .with_label("match on them to format what they hold"),
+                );
+            }
+            Type::Function(..) => {
+                self.errors.push(
+                    Diagnostic::new("functions can't be printed", expr.span)
+                        .with_label("call it to print what it gives"),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn location(&self, span: Span) -> String {
+        let (line, col) = line_col(self.source, span.start);
+        format!("line {}, column {}", line, col)
+    }
+
+    // `function` is the function `body` belongs to, for its `return`s
+    fn collect(&mut self, body: &'a Program, function: Option<&'a str>) {
+        for stmt in body {
+            match &stmt.kind {
+                StmtKind::FunctionDefinition {
+                    name,
+                    args,
+                    returns,
+                    body,
+                    ..
+                } => {
+                    self.function(name, args, returns.as_ref(), body);
+                    continue;
+                }
+                StmtKind::Declaration { name, value } | StmtKind::Assignment { name, value } => {
+                    self.give(name, value)
+                }
+                StmtKind::For { ident, exprs, .. } => {
+                    self.values.entry(ident).or_default();
+                    for expr in exprs {
+                        self.give(ident, expr);
+                    }
+                }
+                StmtKind::ForRange { ident, .. } => {
+                    self.values.entry(ident).or_default();
+                    for expr in stmt.exprs() {
+                        self.give(ident, expr);
+                    }
+                }
+                StmtKind::ForEach { ident, array, .. } => {
+                    self.values.entry(ident).or_default();
+                    self.iterated.entry(ident).or_default().push(array);
+                    self.used_as_array(array);
+                }
+                StmtKind::Return(value) => {
+                    if let Some(f) = function.and_then(|f| self.functions.get_mut(f)) {
+                        f.values.push(value);
+                    }
+                }
+                StmtKind::IndexAssignment { array, value, .. }
+                | StmtKind::Push { array, value } => {
+                    if let Some((name, depth)) = place(array) {
+                        self.elements.entry(name).or_default().push((depth, value));
+                    }
+                }
+                StmtKind::Struct { name, fields } => {
+                    self.structs.insert(
+                        name,
+                        Struct {
+                            fields,
+                            span: stmt.span,
+                        },
+                    );
+                }
+                StmtKind::FieldAssignment {
+                    target,
+                    field,
+                    value,
+                } => self.assigned.push((target, field, value)),
+                StmtKind::Enum { name, variants } => {
+                    for (variant, fields) in variants {
+                        self.structs.insert(
+                            variant,
+                            Struct {
+                                fields,
+                                span: stmt.span,
+                            },
+                        );
+                    }
+                    let variants = variants.iter().map(|(v, _)| v.as_str()).collect();
+                    self.enums.insert(name, variants);
+                }
+                StmtKind::Match { value, arms } => {
+                    for arm in arms {
+                        self.bind(&arm.pattern, value);
+                    }
+                }
+                _ => {}
+            }
+            for expr in stmt.exprs() {
+                self.collect_expr(expr);
+            }
+            for block in stmt.blocks() {
+                self.collect(block, function);
+            }
+        }
+    }
+
+    fn collect_expr(&mut self, expr: &'a Expr) {
+        match &expr.kind {
+            ExprKind::Call { name, args } => self.calls.push((name, args)),
+            ExprKind::Struct { name, fields } | ExprKind::Variant { name, fields, .. } => {
+                for (field, value) in fields {
+                    self.fields.entry((name, field)).or_default().push(value);
+                }
+            }
+            ExprKind::Match { value, arms } => {
+                for arm in arms {
+                    self.bind(&arm.pattern, value);
+                }
+            }
+            ExprKind::Index { array, .. } | ExprKind::Len(array) => self.used_as_array(array),
+            ExprKind::Field { expr, name } => {
+                if let ExprKind::Ident(variable) = &expr.kind {
+                    self.uses
+                        .entry(variable)
+                        .or_default()
+                        .push(Use::Field(name));
+                }
+            }
+            ExprKind::Function(name) => {
+                self.escaping.insert(name);
+            }
+            ExprKind::Lambda {
+                name,
+                args,
+                returns,
+                body,
+                ..
+            } => {
+                self.escaping.insert(name);
+                self.function(name, args, returns.as_ref(), body);
+            }
+            _ => {}
+        }
+        for child in expr.children() {
+            self.collect_expr(child);
+        }
+    }
+
+    fn function(
+        &mut self,
+        name: &'a str,
+        params: &'a [Param],
+        returns: Option<&'a Annotation>,
+        body: &'a Program,
+    ) {
+        for param in params {
+            self.values.entry(&param.name).or_default();
+            if let Some(annotation) = &param.ty {
+                self.annotations.insert(&param.name, annotation);
+            }
+        }
+
+        let mut values = Vec::new();
+        tail_values(body, &mut values);
+        self.functions.insert(
+            name,
+            Function {
+                params,
+                returns,
+                values,
+            },
+        );
+        self.collect(body, Some(name));
+    }
+
+    fn give(&mut self, name: &'a str, value: &'a Expr) {
+        self.values.entry(name).or_default().push(value);
+    }
+
+    fn used_as_array(&mut self, array: &'a Expr) {
+        if let Some((name, depth)) = place(array) {
+            self.uses.entry(name).or_default().push(Use::Array(depth));
+        }
+    }
+
+    // a name a pattern binds as a whole is given the value matched, and one
+    // inside it stands for a field
+    fn bind(&mut self, pattern: &'a Pattern, value: &'a Expr) {
+        if let ExprKind::Ident(name) = &value.kind {
+            self.uses
+                .entry(name)
+                .or_default()
+                .push(Use::Pattern(pattern));
+        }
+        match &pattern.kind {
+            PatternKind::Binding(name) => self.give(name, value),
+            PatternKind::Constructor { name, fields } => self.bind_fields(name, fields),
+            _ => {}
+        }
+    }
+
+    fn bind_fields(&mut self, ctor: &'a str, fields: &'a [Pattern]) {
+        for (i, field) in fields.iter().enumerate() {
+            match &field.kind {
+                PatternKind::Binding(name) => {
+                    self.values.entry(name).or_default();
+                    self.bound.insert(name, (ctor, i));
+                }
+                PatternKind::Constructor { name, fields } => self.bind_fields(name, fields),
+                _ => {}
+            }
+        }
+    }
+}
+
+// whether the struct or enum `name` holds a `target` in one of its fields, or
+// in theirs, and the fields it is in, which for an enum are written along
+// with their variant
+fn holds<'t>(
+    name: &'t str,
+    target: &str,
+    types: &'t Types,
+    seen: &mut Vec<&'t str>,
+    path: &mut Vec<String>,
+) -> bool {
+    if seen.contains(&name) {
+        return false;
+    }
+    seen.push(name);
+    let fields = match types.enums.get(name) {
+        Some(variants) => variants
+            .iter()
+            .flat_map(|v| {
+                let fields = &types.structs[v];
+                fields
+                    .iter()
+                    .map(move |(f, ty)| (format!("{}.{}", v, f), ty))
+            })
+            .collect::<Vec<_>>(),
+        None => types.structs[name]
+            .iter()
+            .map(|(f, ty)| (f.clone(), ty))
+            .collect(),
+    };
+    for (field, ty) in fields {
+        if let Type::Struct(inner) | Type::Enum(inner) = ty {
+            path.push(field);
+            if inner == target || holds(inner, target, types, seen, path) {
+                return true;
+            }
+            path.pop();
+        }
+    }
+    false
+}
+
+// the values a function body finishes with, see `Compiler::compile_body`
+fn tail_values<'a>(body: &'a Program, values: &mut Vec<&'a Expr>) {
+    match body.last().map(|stmt| &stmt.kind) {
+        Some(StmtKind::Expression(expr)) => values.push(expr),
+        Some(StmtKind::IfStatement {
+            body,
+            branch: Some(branch),
+            ..
+        }) => {
+            tail_values(body, values);
+            tail_values(branch, values);
+        }
+        Some(StmtKind::Match { arms, .. }) => {
+            for arm in arms {
+                tail_values(&arm.body, values);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn operator(kind: &ExprKind) -> &'static str {
+    match kind {
+        ExprKind::Add(..) | ExprKind::Pos(_) => "+",
+        ExprKind::Sub(..) | ExprKind::Neg(_) => "-",
+        ExprKind::Mul(..) => "*",
+        ExprKind::Div(..) => "/",
+        ExprKind::Pow(..) => "^",
+        ExprKind::Mod(..) => "%",
+        ExprKind::Leq(..) => "<=",
+        ExprKind::Geq(..) => ">=",
+        ExprKind::Lt(..) => "<",
+        ExprKind::Gt(..) => ">",
+        ExprKind::Eq(..) => "==",
+        ExprKind::Neq(..) => "!=",
+        ExprKind::And(..) => "and",
+        ExprKind::Or(..) => "or",
+        ExprKind::Xor(..) => "xor",
+        ExprKind::Not(_) => "!",
+        _ => unreachable!("not an operator"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::resolver::Resolver;
+
+    fn check(source: &str) -> Result<Types, Vec<Diagnostic>> {
+        let (mut program, _) = parse(source).expect("parses");
+        Resolver::resolve(&mut program, source).expect("resolves");
+        Checker::check(&program, source)
+    }
+
+    // the type of the variable or the return type of the function named
+    // `name` in the source
+    fn type_named(types: &Types, name: &str) -> String {
+        let named = |(unique, ty): (&String, &Type)| {
+            (original_name(unique) == name).then(|| ty.to_string())
+        };
+        types
+            .variables
+            .iter()
+            .find_map(named)
+            .or_else(|| types.returns.iter().find_map(named))
+            .unwrap_or_else(|| panic!("nothing is named {}", name))
+    }
+
+    fn typed(source: &str, name: &str) -> String {
+        match check(source) {
+            Ok(types) => type_named(&types, name),
+            Err(errors) => panic!("{} has errors: {:?}", source, errors),
+        }
+    }
+
+    fn error(source: &str) -> String {
+        match check(source) {
+            Ok(_) => panic!("{} checks", source),
+            Err(errors) => errors[0].message.clone(),
+        }
+    }
+
+    #[test]
+    fn ints_and_floats() {
+        assert_eq!(typed("local x = 1", "x"), "int");
+        assert_eq!(typed("local x = 7 / 2", "x"), "int");
+        assert_eq!(typed("local x = 2 ^ 2", "x"), "float");
+        assert_eq!(typed("local x = 1 + 0.5", "x"), "float");
+        // a variable is a float if anything it is given is
+        assert_eq!(typed("local x = 1\nx = 2.5", "x"), "float");
+        assert_eq!(typed("local a = [1, 2]\na[0] = 2.5", "a"), "[float]");
+        assert_eq!(typed("local a = [1]\npush(a, 0.5)", "a"), "[float]");
+        assert_eq!(typed("half(n) = n / 2\nhalf(3)\nhalf(0.5)", "n"), "float");
+        assert_eq!(typed("half(n) = n / 2\nhalf(3)", "half"), "int");
+        assert_eq!(
+            typed("count(n) do\nif n < 1 do\nreturn 0\nend\n0.5\nend", "count"),
+            "float"
+        );
+    }
+
+    #[test]
+    fn bools() {
+        assert_eq!(typed("local b = 1 < 2", "b"), "bool");
+        assert_eq!(typed("local b = 1 == 1.0 and !false", "b"), "bool");
+        assert_eq!(
+            error("local b = true\nb = 1"),
+            "b is a bool, but this is an int"
+        );
+        assert_eq!(
+            error("print(1 < true)"),
+            "`<` needs a number, but this is a bool"
+        );
+        assert_eq!(
+            error("print(true + 1)"),
+            "`+` needs a number, but this is a bool"
+        );
+        assert_eq!(
+            error("local x = 1\nif x do\nprint(x)\nend"),
+            "an `if` needs a bool, but this is an int"
+        );
+        assert_eq!(
+            error("print(1 == true)"),
+            "`==` compares an int with a bool"
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(typed("f(x: float) = x\nf(1)", "x"), "float");
+        assert_eq!(typed("f(x) -> float = x\nf(1)", "f"), "float");
+        assert_eq!(typed("local a = array(3, 0)\na[0] = 1.5", "a"), "[float]");
+        assert_eq!(
+            error("f(x: int) = x\nf(2.5)"),
+            "x is an int, but this is a float"
+        );
+        assert_eq!(
+            error("f() -> int = 2.5"),
+            "function f returns an int, but this is a float"
+        );
+    }
+
+    #[test]
+    fn parameters_by_use() {
+        let point = "struct P do x, y: float end\n";
+        assert_eq!(typed(&format!("{}get(p) = p.x", point), "p"), "P");
+        assert_eq!(typed(&format!("{}get(p) = p.y", point), "get"), "float");
+        assert_eq!(typed("first(a) = a[0]", "a"), "[int]");
+        assert_eq!(typed("at(g, i, j) = g[i][j]", "g"), "[[int]]");
+        assert_eq!(typed("size(a) = len(a)", "a"), "[int]");
+        assert_eq!(
+            typed(
+                "sum(a) do\nlocal t = 0\nfor x in a do\nt = t + x\nend\nt\nend",
+                "a"
+            ),
+            "[int]"
+        );
+        assert_eq!(
+            typed(
+                "enum S do C(r), N end\narea(s) = match s do C(r) => r, N => 0 end",
+                "s"
+            ),
+            "S"
+        );
+        assert_eq!(
+            typed("neg(b) = match b do true => false, _ => true end", "b"),
+            "bool"
+        );
+        // what it is given still decides when it is called
+        assert_eq!(
+            typed("first(a) = a[0]\nprint(first([0.5]))", "a"),
+            "[float]"
+        );
+
+        assert_eq!(
+            error("struct P do x end\nstruct Q do x end\nget(p) = p.x"),
+            "cannot infer the type of `p`, annotate it"
+        );
+        assert_eq!(
+            error("first(a) = a[0]\nprint(first(1))"),
+            "indexing needs an array, but this is an int"
+        );
+    }
+
+    #[test]
+    fn function_values() {
+        assert_eq!(
+            typed("f(x: int) = x * 2\nlocal g = f", "g"),
+            "fn(int) -> int"
+        );
+        assert_eq!(
+            typed("local g = fn(a, b: int) -> bool => a < b", "g"),
+            "fn(float, int) -> bool"
+        );
+        assert_eq!(
+            typed(
+                "apply(h: fn(int) -> int, x) = h(x)\nprint(apply(fn(n: int) -> int => n, 1))",
+                "apply"
+            ),
+            "int"
+        );
+        assert_eq!(typed("f(x) = x\nlocal g = f\nlocal y = g(1)", "y"), "float");
+
+        assert_eq!(
+            error("f(x: int) = x\nlocal g = f\nprint(g(7.5))"),
+            "`g` takes an int for this argument, but this is a float"
+        );
+        assert_eq!(
+            error("f(x) = x\nlocal g = f\nprint(g(1, 2))"),
+            "`g` takes 1 argument but 2 were given"
+        );
+        assert_eq!(
+            error("local y = 0.0\nprint(y(9))"),
+            "only functions can be called, but this is a float"
+        );
+        assert_eq!(
+            error("f(x) = x\nlocal g = f\nprint(g + 1)"),
+            "`+` needs a number, but this is a `fn(float) -> float`"
+        );
+        assert_eq!(
+            error("f(x) = x\nlocal g = f\nprint(g)"),
+            "functions can't be printed"
+        );
+        assert_eq!(error("f(x) = x\nf"), "functions can't be printed");
+    }
+}
@@ -1,47 +1,226 @@
+use crate::checker::{type_of, Types};
+use crate::diagnostics::Diagnostic;
 use crate::types::*;
 use crate::utils::*;
 use lasso::{Rodeo, Spur};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::write;
 use std::io::Write;
 use std::path::Path;
 use std::process::Command;
 use std::process::Stdio;
 
+// the C is read from stdin; signed overflow wraps and floats are rounded
+// after every operation, as they are in the interpreter
+const GCC_FLAGS: [&str; 7] = ["-x", "c", "-O3", "-fwrapv", "-ffp-contract=off", "-", "-lm"];
+
 const C_HEADER: &str = r#"#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
 #include <math.h>
+#include <stdbool.h>
 
-void print_number(double n){
+// `text` is written first, once `n` has been evaluated
+void cx_print_number(const char *text, double n, int width){
+	fputs(text, stdout);
 	if (n==(long long)n) {
-		printf("%lld\n", (long long)n);
+		printf("%*lld", width, (long long)n);
 	} else {
-		printf("%lf\n", (double)n);
+		printf("%*lf", width, (double)n);
 	}
+}
+
+// stops the program, after what it has printed so far, with the error and
+// the rest of the diagnostic the interpreter would give, which is where in
+// the source it went wrong
+void cx_fail(const char *message, const char *location){
+	fflush(stdout);
+	fprintf(stderr, "error: %s\n%s", message, location);
+	exit(1);
+}
+
+// integer division and remainder round towards zero; dividing the smallest
+// int by -1 wraps instead of trapping
+long long cx_div(long long a, long long b, const char *location){
+	if (b==0) cx_fail("integer division by zero", location);
+	return b==-1?-a:a/b;
+}
+
+long long cx_mod(long long a, long long b, const char *location){
+	if (b==0) cx_fail("integer division by zero", location);
+	return b==-1?0:a%b;
+}
+
+// how many times a range of floats runs, counted up front so each value is
+// `start+k*step` rather than a sum that builds up rounding error; a count
+// within rounding of a whole number is taken to be it, so `0..=0.3 step 0.1`
+// still reaches 0.3
+double cx_range_count(double start, double stop, double step, bool inclusive){
+	double n=(stop-start)/step, whole=round(n);
+	if (fabs(n-whole)<=1e-9*fmax(1, fabs(n))) n=whole;
+	n=inclusive?floor(n)+1:ceil(n);
+	return n>0?n:0;
+}
+
+// a function value: a wrapper taking the captured variables of the function
+// as a `void *` followed by its arguments, cast to its own type when called
+typedef struct {
+	void (*fn)(void);
+	void *env;
+} cx_fn;
+
+void *cx_box(const void *p, size_t n){
+	void *box=malloc(n);
+	memcpy(box, p, n);
+	return box;
+}
+
+// arrays are shared by pointer, and their elements are zeroed when they are
+// made and doubled in space when they are pushed to and full
+typedef struct {
+	long long len, cap;
+	void *data;
+} cx_array;
+
+cx_array *cx_array_new(long long len, size_t size){
+	cx_array *a=malloc(sizeof(cx_array));
+	a->len=a->cap=len;
+	a->data=calloc(len?len:1, size);
+	return a;
+}
+
+// the length given to `array`, which can't be negative
+long long cx_length(long long len, const char *location){
+	if (len<0) {
+		fflush(stdout);
+		fprintf(stderr, "error: an array can't have a length of %lld\n%s", len, location);
+		exit(1);
+	}
+	return len;
+}
+
+cx_array *cx_array_of(long long len, size_t size, const void *elements){
+	cx_array *a=cx_array_new(len, size);
+	memcpy(a->data, elements, len*size);
+	return a;
+}
+
+// the length is checked once the value has been evaluated too
+cx_array *cx_array_fill(long long len, size_t size, const void *value, const char *location){
+	cx_array *a=cx_array_new(cx_length(len, location), size);
+	for (long long i=0;i<len;i++) {
+		memcpy((char*)a->data+i*size, value, size);
+	}
+	return a;
+}
+
+// the element `i` of `a`, which stops the program with where `array[i]` is
+// in the source if `i` is out of bounds
+void *cx_at(cx_array *a, long long i, size_t size, const char *array, const char *location){
+	if (i<0 || i>=a->len) {
+		fflush(stdout);
+		fprintf(stderr, "error: index %lld is out of bounds for `%s`, which has a length of %lld\n%s", i, array, a->len, location);
+		exit(1);
+	}
+	return (char*)a->data+i*size;
+}
+
+// the space for the pushed element
+void *cx_push(cx_array *a, size_t size){
+	if (a->len==a->cap) {
+		a->cap=a->cap?a->cap*2:4;
+		a->data=realloc(a->data, a->cap*size);
+	}
+	return (char*)a->data+a->len++*size;
 }"#;
 
-pub struct Compiler {
-    ref_env: HashMap<Spur, String>,
+// structs are C structs, copied like any other value, with their fields
+// named `f_` and the name of the field. Enums are tagged unions: a `tag` that
+// is one of the `k_` constants of the variants, and a union `as` holding the
+// fields of each variant that has any as a struct `v_` and its name.
+fn c_type(ty: &Type) -> String {
+    match ty {
+        Type::Int => String::from("long long"),
+        Type::Float => String::from("double"),
+        Type::Bool => String::from("bool"),
+        Type::Array(_) => String::from("cx_array *"),
+        Type::Struct(name) => format!("struct s_{}", name),
+        Type::Enum(name) => format!("struct e_{}", name),
+        Type::Function(..) => String::from("cx_fn"),
+    }
+}
+
+// a name for `ty` in the names of generated functions
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Array(element) => format!("array_of_{}", type_name(element)),
+        Type::Struct(name) => format!("struct_{}", name),
+        Type::Enum(name) => format!("enum_{}", name),
+        Type::Function(params, returns) => format!(
+            "fn_{}_to_{}",
+            params
+                .iter()
+                .map(type_name)
+                .collect::<Vec<_>>()
+                .join("_and_"),
+            type_name(returns)
+        ),
+        _ => ty.to_string(),
+    }
+}
+
+struct FunctionRef {
+    name: String,
+    args: Vec<String>,
+    returns: Type,
+    captures: Vec<String>,
+}
+
+pub struct Compiler<'a> {
+    ref_env: HashMap<Spur, FunctionRef>,
     rodeo: Rodeo,
     functions: Vec<String>,
+    prototypes: Vec<String>,
+    // function value wrappers, generated as they are needed
+    runtime: Vec<String>,
+    generated: HashSet<String>,
+    mangler: Mangler,
+    path: Vec<String>,
+    types: &'a Types,
+    // variables the function being compiled reaches through its `env`
+    captured: HashSet<String>,
+    source: &'a str,
+    filename: &'a str,
+    // whether array indexes are checked against the length
+    checked: bool,
 }
 
-impl Compiler {
-    pub fn new() -> Self {
+impl<'a> Compiler<'a> {
+    pub fn new(types: &'a Types, source: &'a str, filename: &'a str, checked: bool) -> Self {
         Self {
             ref_env: HashMap::new(),
             rodeo: Rodeo::default(),
             functions: Vec::new(),
+            prototypes: Vec::new(),
+            runtime: Vec::new(),
+            generated: HashSet::new(),
+            mangler: Mangler::default(),
+            path: Vec::new(),
+            types,
+            captured: HashSet::new(),
+            source,
+            filename,
+            checked,
         }
     }
 
-    pub fn compile(prog: &Program, args: crate::Args) -> anyhow::Result<()> {
-        let mut c = Compiler::new();
-
-        let code = c.compile_program(&prog, "main", &vec![])?;
-
-        let functions = c.functions.join("\n");
-
-        let program = format!("{}\n{}\n{}", C_HEADER, functions, code);
+    pub fn compile(
+        prog: &Program,
+        types: &'a Types,
+        source: &'a str,
+        args: crate::Args,
+    ) -> anyhow::Result<()> {
+        let program = Compiler::generate(prog, types, source, &args.filename, !args.unchecked)?;
 
         let input_filestem = Path::new(&args.filename)
             .file_stem()
@@ -54,30 +233,25 @@ impl Compiler {
         }
 
         let mut cmd = Command::new("gcc");
-        cmd.args(&[
-            "-o",
-            &if let Some(filename) = args.output {
+        cmd.arg("-o")
+            .arg(if let Some(filename) = args.output {
                 filename
             } else {
                 input_filestem
-            },
-            "-x",
-            "c",
-            "-O3",
-            "-",
-        ])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+            })
+            .args(GCC_FLAGS)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
 
         let mut child = cmd.spawn().expect("could not start gcc");
 
-        let stdin = child.stdin.as_mut().unwrap();
-
-        stdin.write_all(program.as_bytes())?;
+        let written = child.stdin.take().unwrap().write_all(program.as_bytes());
 
         let output = child.wait_with_output()?;
 
+        written?;
+
         if output.status.success() {
             println!("{}", String::from_utf8_lossy(&output.stdout));
         } else {
@@ -90,92 +264,366 @@ impl Compiler {
         Ok(())
     }
 
-    pub fn compile_program(
-        &mut self,
+    // the C source of `prog`
+    fn generate(
         prog: &Program,
-        fn_name: impl AsRef<str>,
-        args: &Vec<String>,
+        types: &'a Types,
+        source: &'a str,
+        filename: &'a str,
+        checked: bool,
     ) -> anyhow::Result<String> {
-        if self.rodeo.contains(fn_name.as_ref().clone()) {
-            return Err(anyhow::anyhow!(
-                "function {} already exists",
-                fn_name.as_ref()
-            ));
+        let mut c = Compiler::new(types, source, filename, checked);
+
+        let structs = c.declare_structs(prog);
+
+        c.declare_functions(prog)?;
+
+        let code = c.compile_program(prog, None)?;
+
+        let prototypes = c.prototypes.join("");
+
+        let runtime = c.runtime.join("");
+
+        let functions = c.functions.join("\n");
+
+        Ok(format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            C_HEADER, structs, prototypes, runtime, functions, code
+        ))
+    }
+
+    // the structs and enums of the program, each after the ones it holds,
+    // which C needs to know the size of
+    fn declare_structs(&self, prog: &Program) -> String {
+        let mut declared = Vec::new();
+        let mut structs = String::new();
+        for stmt in prog {
+            if let StmtKind::Struct { name, .. } | StmtKind::Enum { name, .. } = &stmt.kind {
+                self.declare_struct(name, &mut declared, &mut structs);
+            }
         }
+        structs
+    }
 
-        let mut program = if fn_name.as_ref() == "main" {
-            String::from("int main(){\n")
+    fn declare_struct<'t>(
+        &'t self,
+        name: &'t str,
+        declared: &mut Vec<&'t str>,
+        structs: &mut String,
+    ) {
+        if declared.contains(&name) {
+            return;
+        }
+        declared.push(name);
+
+        let Some(variants) = self.types.enums.get(name) else {
+            let fields = self.declare_fields(name, declared, structs);
+            structs.push_str(&format!("struct s_{}{{{}}};\n", name, fields));
+            return;
+        };
+
+        let mut union = String::new();
+        for variant in variants {
+            let fields = self.declare_fields(variant, declared, structs);
+            if !fields.is_empty() {
+                structs.push_str(&format!("struct s_{}{{{}}};\n", variant, fields));
+                union.push_str(&format!("struct s_{} v_{0};", variant));
+            }
+        }
+        let tags = variants
+            .iter()
+            .map(|variant| format!("k_{}", variant))
+            .collect::<Vec<_>>();
+        structs.push_str(&format!("enum{{{}}};\n", tags.join(",")));
+        if union.is_empty() {
+            structs.push_str(&format!("struct e_{}{{int tag;}};\n", name));
         } else {
-            let key = self.rodeo.get_or_intern(fn_name.as_ref());
-            let name = generate_function_name(fn_name.as_ref());
-            self.ref_env.insert(key, name.clone());
+            structs.push_str(&format!(
+                "struct e_{}{{int tag;union{{{}}}as;}};\n",
+                name, union
+            ));
+        }
+    }
 
-            format!(
-                "double {}({}){{\n",
-                name,
-                args.into_iter()
-                    .map(|s| format!("double {}", s))
-                    .collect::<Vec<_>>()
-                    .join(",")
-            )
+    // the fields of the struct or variant `name`, declaring what they hold
+    // first
+    fn declare_fields<'t>(
+        &'t self,
+        name: &'t str,
+        declared: &mut Vec<&'t str>,
+        structs: &mut String,
+    ) -> String {
+        let fields = &self.types.structs[name];
+        for (_, ty) in fields {
+            if let Type::Struct(inner) | Type::Enum(inner) = ty {
+                self.declare_struct(inner, declared, structs);
+            }
+        }
+        fields
+            .iter()
+            .map(|(field, ty)| format!("{} f_{};", c_type(ty), field))
+            .collect()
+    }
+
+    // every function gets its C name and a prototype before any code is
+    // generated, so functions can be called before they are defined and can
+    // recurse into each other
+    fn declare_functions(&mut self, body: &Program) -> anyhow::Result<()> {
+        for stmt in body {
+            match &stmt.kind {
+                StmtKind::FunctionDefinition {
+                    name,
+                    args,
+                    body,
+                    captures,
+                    ..
+                } => {
+                    if self.rodeo.contains(name) {
+                        return Err(anyhow::anyhow!("function {} already exists", name));
+                    }
+
+                    self.declare_function(name, args, body, captures)?;
+                }
+                _ => {
+                    for block in stmt.blocks() {
+                        self.declare_functions(block)?;
+                    }
+                }
+            }
+            for expr in stmt.exprs() {
+                self.declare_lambdas(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn declare_lambdas(&mut self, expr: &Expr) -> anyhow::Result<()> {
+        if let ExprKind::Lambda {
+            name,
+            args,
+            body,
+            captures,
+            ..
+        } = &expr.kind
+        {
+            self.declare_function(name, args, body, captures)?;
+        }
+        for child in expr.children() {
+            self.declare_lambdas(child)?;
+        }
+
+        Ok(())
+    }
+
+    fn declare_function(
+        &mut self,
+        name: &str,
+        args: &[Param],
+        body: &Program,
+        captures: &[String],
+    ) -> anyhow::Result<()> {
+        let f = FunctionRef {
+            name: self.mangler.function(&self.path, name),
+            args: args.iter().map(|arg| arg.name.clone()).collect(),
+            returns: self.types.returns(name),
+            captures: captures.to_vec(),
+        };
+        self.prototypes.push(self.prototype(&f));
+
+        let key = self.rodeo.get_or_intern(name);
+        self.ref_env.insert(key, f);
+
+        self.path.push(String::from(name));
+        let declared = self.declare_functions(body);
+        self.path.pop();
+        declared
+    }
+
+    fn c_type(&self, name: &str) -> String {
+        c_type(&self.types.variable(name))
+    }
+
+    // the C type of the elements of the array `expr`
+    fn element_type(&self, expr: &Expr) -> String {
+        match type_of(expr, self.types) {
+            Type::Array(element) => c_type(&element),
+            _ => unreachable!("the checker only allows arrays to be indexed"),
+        }
+    }
+
+    fn is_int(&self, expr: &Expr) -> bool {
+        type_of(expr, self.types) == Type::Int
+    }
+
+    // whether evaluating `expr` could print, change a variable or stop the
+    // program, so it has to stay in order with what is evaluated around it
+    fn has_effects(&self, expr: &Expr) -> bool {
+        let effect = match &expr.kind {
+            ExprKind::Call { .. }
+            | ExprKind::CallValue { .. }
+            | ExprKind::Index { .. }
+            | ExprKind::NewArray { .. } => true,
+            ExprKind::Div(..) | ExprKind::Mod(..) => self.is_int(expr),
+            _ => false,
         };
+        effect || expr.children().into_iter().any(|e| self.has_effects(e))
+    }
 
-        program.push_str(&self.compile_body(
-            prog,
-            if fn_name.as_ref() == "main" {
-                false
+    // `exprs` compiled to be evaluated left to right, as in the interpreter,
+    // which C leaves unspecified for the operands of an operator and the
+    // arguments of a call. Every operand before the last one with effects is
+    // put in a temporary first, by the declarations given with them, which
+    // go at the start of a GNU statement expression.
+    fn operands(&mut self, exprs: &[&Expr]) -> anyhow::Result<(String, Vec<String>)> {
+        let last = exprs.iter().rposition(|e| self.has_effects(e)).unwrap_or(0);
+        let mut temporaries = String::new();
+        let mut compiled = Vec::new();
+        for (i, e) in exprs.iter().enumerate() {
+            let value = self.compile_expr(e)?;
+            if i < last {
+                let ty = c_type(&type_of(e, self.types));
+                temporaries.push_str(&format!("{} cx_a{}={};", ty, i, value));
+                compiled.push(format!("cx_a{}", i));
             } else {
-                true
-            },
-        )?);
+                compiled.push(value);
+            }
+        }
+        Ok((temporaries, compiled))
+    }
+
+    // `lhs` and then `rhs`, put together by `op`
+    fn binary(
+        &mut self,
+        lhs: &Expr,
+        rhs: &Expr,
+        op: impl FnOnce(&str, &str) -> String,
+    ) -> anyhow::Result<String> {
+        let (temporaries, operands) = self.operands(&[lhs, rhs])?;
+        Ok(sequenced(&temporaries, op(&operands[0], &operands[1])))
+    }
+
+    // captured variables are passed by reference in a struct of pointers
+    fn env_struct(&self, f: &FunctionRef) -> String {
+        format!(
+            "struct env_{}{{{}}};\n",
+            f.name,
+            f.captures
+                .iter()
+                .map(|c| format!("{} *{};", self.c_type(c), mangle_variable(c)))
+                .collect::<String>()
+        )
+    }
+
+    fn signature(&self, f: &FunctionRef) -> String {
+        let mut params = Vec::new();
+        if !f.captures.is_empty() {
+            params.push(format!("struct env_{} *env", f.name));
+        }
+        params.extend(
+            f.args
+                .iter()
+                .map(|s| format!("{} {}", self.c_type(s), mangle_variable(s))),
+        );
 
-        if fn_name.as_ref() == "main" {
-            program.push_str("\treturn 0;\n");
+        format!("{} {}({})", c_type(&f.returns), f.name, params.join(","))
+    }
+
+    fn prototype(&self, f: &FunctionRef) -> String {
+        if f.captures.is_empty() {
+            format!("{};\n", self.signature(f))
+        } else {
+            format!("{}{};\n", self.env_struct(f), self.signature(f))
         }
+    }
+
+    fn variable(&self, name: &str) -> String {
+        if self.captured.contains(name) {
+            format!("(*env->{})", mangle_variable(name))
+        } else {
+            mangle_variable(name)
+        }
+    }
+
+    pub fn compile_program(
+        &mut self,
+        prog: &Program,
+        fn_name: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let fn_name = match fn_name {
+            Some(fn_name) => fn_name,
+            None => {
+                return Ok(format!(
+                    "int main(){{\n{}\treturn 0;\n}}\n",
+                    self.compile_body(prog, false)?
+                ))
+            }
+        };
+
+        let key = self.rodeo.get(fn_name).unwrap();
+        let f = &self.ref_env[&key];
+        let mut program = format!("{}{{\n", self.signature(f));
 
+        let captured = f.captures.iter().cloned().collect();
+        let outer = std::mem::replace(&mut self.captured, captured);
+        let body = self.compile_body(prog, true);
+        self.captured = outer;
+
+        program.push_str(&body?);
         program.push_str("}\n");
 
         Ok(program)
     }
 
-    fn compile_body(&mut self, body: &Program, function_body: bool) -> anyhow::Result<String> {
+    // `tail` blocks end a function body, so their value is returned: that of
+    // their last expression, or of a final `if` with an `else`
+    fn compile_body(&mut self, body: &Program, tail: bool) -> anyhow::Result<String> {
         let mut program = String::new();
 
         let count = body.len();
 
-        for (i, stmt) in body.into_iter().enumerate() {
-            match stmt {
-                Stmt::Expression(ref expr) => {
-                    if i == count - 1 && function_body == true {
+        for (i, stmt) in body.iter().enumerate() {
+            let tail = tail && i == count - 1;
+            match &stmt.kind {
+                StmtKind::Expression(ref expr) => {
+                    if tail {
                         program.push_str(&format!("\treturn {};\n", self.compile_expr(expr)?));
                     } else {
-                        program.push_str(&format!(
-                            "\tprint_number({});\n",
-                            self.compile_expr(expr)?
-                        ));
+                        // printed as `print` would
+                        let arg = (&**expr, Format::default());
+                        program.push_str(&self.compile_print([arg])?);
                     }
                 }
-                Stmt::FunctionDefinition {
-                    ref name,
-                    ref args,
-                    ref body,
-                } => {
-                    let f = self.compile_program(body, name, args)?;
+                StmtKind::Print(args) => {
+                    let args = args.iter().map(|(arg, format)| (arg, *format));
+                    program.push_str(&self.compile_print(args)?)
+                }
+                StmtKind::FunctionDefinition { name, body, .. } => {
+                    let f = self.compile_program(body, Some(name))?;
                     self.functions.push(f);
                 }
-                Stmt::Declaration {
+                StmtKind::Declaration {
                     ref name,
                     ref value,
                 } => {
-                    program.push_str(&format!("\tdouble {}={};\n", name, self.compile_expr(value)?));
+                    program.push_str(&format!(
+                        "\t{} {}={};\n",
+                        self.c_type(name),
+                        mangle_variable(name),
+                        self.compile_expr(value)?
+                    ));
                 }
-                Stmt::Assignment {
+                StmtKind::Assignment {
                     ref name,
                     ref value,
                 } => {
-                    program.push_str(&format!("\t{}={};\n", name, self.compile_expr(value)?));
+                    program.push_str(&format!(
+                        "\t{}={};\n",
+                        self.variable(name),
+                        self.compile_expr(value)?
+                    ));
                 }
-                Stmt::IfStatement {
+                StmtKind::IfStatement {
                     ref cond,
                     ref body,
                     ref branch,
@@ -184,8 +632,8 @@ impl Compiler {
                         Some(b) => program.push_str(&format!(
                             "\tif ({}){{\n{}\t}} else {{\n{}\t}}\n",
                             self.compile_expr(cond)?,
-                            self.compile_body(body, false)?,
-                            self.compile_body(b, false)?
+                            self.compile_body(body, tail)?,
+                            self.compile_body(b, tail)?
                         )),
                         None => program.push_str(&format!(
                             "\tif ({}){{\n{}\t}}\n",
@@ -194,27 +642,31 @@ impl Compiler {
                         )),
                     };
                 }
-                Stmt::For {
+                StmtKind::For {
                     ref body,
                     ref ident,
                     ref exprs,
                 } => program.push_str(&match exprs.len() {
                     1 => format!(
-                        "\tfor (int {}=0;{0}<{};{0}++){{\n{}\t}}\n",
-                        ident,
+                        "\tfor ({} {}=0;{1}<{};{1}++){{\n{}\t}}\n",
+                        self.c_type(ident),
+                        mangle_variable(ident),
                         self.compile_expr(&exprs[0])?,
                         self.compile_body(body, false)?
                     ),
+                    // start>stop?i>stop:i<stop
                     2 => format!(
-                        "\tfor (int {}=(int){};{1}>{2}?(int){0}>(int){2}:(int){0}<(int){};{1}>{2}?{0}--:{0}++){{\n{}\t}}\n",
-                        ident,
+                        "\tfor ({} {}={};{2}>{3}?{1}>{3}:{1}<{};{2}>{3}?{1}--:{1}++){{\n{}\t}}\n",
+                        self.c_type(ident),
+                        mangle_variable(ident),
                         self.compile_expr(&exprs[0])?,
                         self.compile_expr(&exprs[1])?,
                         self.compile_body(body, false)?
-                    ), // start>stop?i>stop:i<stop
+                    ),
                     3 => format!(
-                        "\tfor (int {}=(int){};{1}>{2}?(int){0}>(int){2}:(int){0}<(int){};{0}+=(int){}){{\n{}\t}}\n",
-                        ident,
+                        "\tfor ({} {}={};{2}>{3}?{1}>{3}:{1}<{};{1}+={}){{\n{}\t}}\n",
+                        self.c_type(ident),
+                        mangle_variable(ident),
                         self.compile_expr(&exprs[0])?,
                         self.compile_expr(&exprs[1])?,
                         self.compile_expr(&exprs[2])?,
@@ -222,67 +674,851 @@ impl Compiler {
                     ),
                     _ => unreachable!(),
                 }),
-                Stmt::While { body, expr } => program.push_str(&format!("\twhile ({}){{\n{}\t}}\n", self.compile_expr(expr)?, self.compile_body(body, false)?)),
+                // the bounds and step are evaluated once, before the loop
+                StmtKind::ForRange {
+                    body,
+                    ident,
+                    start,
+                    stop,
+                    inclusive,
+                    step,
+                } => {
+                    if self.types.variable(ident) == Type::Float {
+                        let step = match step {
+                            Some(step) => format!(
+                                "{};\n\tif (cx_step==0) cx_fail(\"a range can't have a step of 0\",{});\n",
+                                self.compile_expr(step)?,
+                                self.location(step.span)
+                            ),
+                            None => String::from("1.0;\n"),
+                        };
+                        program.push_str(&format!(
+                            "\t{{double cx_start={},cx_stop={},cx_step={}\tdouble cx_count=cx_range_count(cx_start,cx_stop,cx_step,{});\n\tfor (double cx_k=0;cx_k<cx_count;cx_k++){{\n\tdouble {}=cx_start+cx_k*cx_step;\n{}\t}}}}\n",
+                            self.compile_expr(start)?,
+                            self.compile_expr(stop)?,
+                            step,
+                            inclusive,
+                            mangle_variable(ident),
+                            self.compile_body(body, false)?
+                        ));
+                        continue;
+                    }
+                    let i = mangle_variable(ident);
+                    let mut header = format!(
+                        "\t{{{} {}={},cx_stop={}",
+                        self.c_type(ident),
+                        i,
+                        self.compile_expr(start)?,
+                        self.compile_expr(stop)?
+                    );
+                    let (lt, gt) = if *inclusive { ("<=", ">=") } else { ("<", ">") };
+                    let (cond, next) = match step {
+                        Some(step) => {
+                            header.push_str(&format!(
+                                ",cx_step={};\n\tif (cx_step==0) cx_fail(\"a range can't have a step of 0\",{});\n",
+                                self.compile_expr(step)?,
+                                self.location(step.span)
+                            ));
+                            (
+                                format!("cx_step>0?{0}{1}cx_stop:{0}{2}cx_stop", i, lt, gt),
+                                format!("{}+=cx_step", i),
+                            )
+                        }
+                        None => {
+                            header.push_str(";\n");
+                            (format!("{}{}cx_stop", i, lt), format!("{}++", i))
+                        }
+                    };
+                    program.push_str(&format!(
+                        "{}\tfor (;{};{}){{\n{}\t}}}}\n",
+                        header,
+                        cond,
+                        next,
+                        self.compile_body(body, false)?
+                    ));
+                }
+                StmtKind::ForEach { body, ident, array } => program.push_str(&format!(
+                    "\t{{cx_array *cx_each={};\n\tfor (long long cx_i=0;cx_i<cx_each->len;cx_i++){{\n\t{} {}=(({}*)cx_each->data)[cx_i];\n{}\t}}}}\n",
+                    self.compile_expr(array)?,
+                    self.c_type(ident),
+                    mangle_variable(ident),
+                    self.element_type(array),
+                    self.compile_body(body, false)?
+                )),
+                StmtKind::While { body, expr } => program.push_str(&format!(
+                    "\twhile ({}){{\n{}\t}}\n",
+                    self.compile_expr(expr)?,
+                    self.compile_body(body, false)?
+                )),
+                StmtKind::Return(expr) => {
+                    program.push_str(&format!("\treturn {};\n", self.compile_expr(expr)?))
+                }
+                // the value is evaluated before the array, as in the
+                // interpreter
+                StmtKind::IndexAssignment {
+                    array,
+                    index,
+                    value,
+                } => {
+                    let ty = self.element_type(array);
+                    let value = self.compile_expr(value)?;
+                    program.push_str(&format!(
+                        "\t{{{} cx_v={};{}=cx_v;}}\n",
+                        ty,
+                        value,
+                        self.compile_index(array, index)?
+                    ));
+                }
+                StmtKind::Push { array, value } => {
+                    let ty = self.element_type(array);
+                    program.push_str(&format!(
+                        "\t{{{} cx_v={};*({0}*)cx_push({},sizeof({0}))=cx_v;}}\n",
+                        ty,
+                        self.compile_expr(value)?,
+                        self.compile_expr(array)?
+                    ));
+                }
+                // the value is evaluated before the struct, like an element
+                StmtKind::FieldAssignment {
+                    target,
+                    field,
+                    value,
+                } => {
+                    let ty = match type_of(target, self.types) {
+                        Type::Struct(name) => c_type(&self.types.field(&name, field).unwrap()),
+                        _ => unreachable!("the checker only allows fields of structs"),
+                    };
+                    let value = self.compile_expr(value)?;
+                    program.push_str(&format!(
+                        "\t{{{} cx_v={};({}).f_{}=cx_v;}}\n",
+                        ty,
+                        value,
+                        self.compile_expr(target)?,
+                        field
+                    ));
+                }
+                StmtKind::Match { value, arms } => {
+                    let head = self.compile_match(value, arms)?;
+                    let mut bodies = Vec::new();
+                    for arm in arms {
+                        bodies.push(format!("{{\n{}\t}}", self.compile_body(&arm.body, tail)?));
+                    }
+                    program.push_str(&format!("\t{{{}{}}}\n", head, dispatch(&bodies)));
+                }
+                StmtKind::Struct { .. } | StmtKind::Enum { .. } => {}
+                StmtKind::Break => program.push_str("\tbreak;\n"),
+                StmtKind::Continue => program.push_str("\tcontinue;\n"),
+            }
+        }
+
+        Ok(program)
+    }
+
+    // a function value over the wrapper for `name`, which takes the
+    // environment as a `void *` so every function value of the same type is
+    // called alike; the captured variables are copied to the heap, as the
+    // value can outlive them
+    fn function_value(&mut self, name: &str) -> String {
+        let f = &self.ref_env[&self.rodeo.get(name).unwrap()];
+        let wrapper = format!("fv_{}", f.name);
+
+        if self.generated.insert(wrapper.clone()) {
+            let mut params = vec![String::from("void *env")];
+            let mut args = Vec::new();
+            if !f.captures.is_empty() {
+                args.push(String::from("env"));
+            }
+            for (i, arg) in f.args.iter().enumerate() {
+                params.push(format!("{} a{}", c_type(&self.types.variable(arg)), i));
+                args.push(format!("a{}", i));
+            }
+            self.runtime.push(format!(
+                "{} {}({}){{return {}({});}}\n",
+                c_type(&f.returns),
+                wrapper,
+                params.join(","),
+                f.name,
+                args.join(",")
+            ));
+        }
+
+        let env = if f.captures.is_empty() {
+            String::from("0")
+        } else {
+            let copies = f
+                .captures
+                .iter()
+                .map(|c| {
+                    let v = self.variable(c);
+                    format!("cx_box(&{},sizeof {})", v, v)
+                })
+                .collect::<Vec<_>>();
+            format!(
+                "cx_box(&(struct env_{}){{{}}},sizeof(struct env_{0}))",
+                f.name,
+                copies.join(",")
+            )
+        };
+
+        format!("((cx_fn){{(void(*)(void)){},{}}})", wrapper, env)
+    }
+
+    // a `printf` for each value, carrying whatever text comes before it, so
+    // the values are still evaluated left to right
+    fn compile_print<'e>(
+        &mut self,
+        args: impl IntoIterator<Item = (&'e Expr, Format)>,
+    ) -> anyhow::Result<String> {
+        let mut program = String::new();
+        // the format string of the next `printf`
+        let mut text = String::new();
+
+        for (i, (arg, format)) in args.into_iter().enumerate() {
+            if i > 0 {
+                text.push(' ');
+            }
+
+            let segments = match &arg.kind {
+                ExprKind::Str(segments) => segments,
+                _ => {
+                    self.compile_value(arg, format, &mut text, &mut program)?;
+                    continue;
+                }
+            };
+
+            let start = text.len();
+            for segment in segments {
+                match segment {
+                    Segment::Text(s) => text.push_str(&s.replace('%', "%%")),
+                    Segment::Value(expr, format) => {
+                        self.compile_value(expr, *format, &mut text, &mut program)?
+                    }
+                }
+            }
+
+            // only strings without interpolations have a width, which is the
+            // same every time so the padding is added here
+            if format.width > 0 {
+                let len = text[start..].replace("%%", "%").len();
+                let padding = " ".repeat(format.width.saturating_sub(len));
+                text.insert_str(start, &padding);
             }
         }
 
+        text.push('\n');
+        program.push_str(&format!("\tprintf({});\n", c_string(&text)));
+
         Ok(program)
     }
 
-    fn compile_expr(&self, expr: &Box<Expr>) -> anyhow::Result<String> {
-        Ok(match (**expr).clone() {
-            Expr::Number(n) => n.to_string(),
-            Expr::Ident(ident) => ident,
-            Expr::Call { name, args } => {
+    fn compile_value(
+        &mut self,
+        expr: &Expr,
+        format: Format,
+        text: &mut String,
+        program: &mut String,
+    ) -> anyhow::Result<()> {
+        let value = self.compile_expr(expr)?;
+        let width = match format.width {
+            0 => String::new(),
+            width => width.to_string(),
+        };
+
+        let ty = type_of(expr, self.types);
+        if let Type::Array(_) | Type::Struct(_) | Type::Enum(_) = ty {
+            let printer = self.printer(&ty);
+            program.push_str(&format!(
+                "\t{}({},{});\n",
+                printer,
+                c_string(&text.replace("%%", "%")),
+                value
+            ));
+            text.clear();
+            return Ok(());
+        }
+        let conversion = match (format.precision, format.scientific) {
+            _ if ty == Type::Bool => "s",
+            (None, false) if ty == Type::Int => "lld",
+            (_, true) => "e",
+            (Some(_), false) => "lf",
+            // without a precision, a float with no fraction prints like an
+            // int, which only the runtime can tell
+            (None, false) => {
+                program.push_str(&format!(
+                    "\tcx_print_number({},{},{});\n",
+                    c_string(&text.replace("%%", "%")),
+                    value,
+                    format.width
+                ));
+                text.clear();
+                return Ok(());
+            }
+        };
+
+        text.push_str(&format!(
+            "%{}{}{}",
+            width,
+            format
+                .precision
+                .map_or(String::new(), |precision| format!(".{}", precision)),
+            conversion
+        ));
+        let value = match conversion {
+            "s" => format!("({})?\"true\":\"false\"", value),
+            "lld" => value,
+            _ => format!("(double)({})", value),
+        };
+        program.push_str(&format!("\tprintf({},{});\n", c_string(text), value));
+        text.clear();
+
+        Ok(())
+    }
+
+    // the start of a `match`: `cx_m` holds the value, and a `switch` on its
+    // tag, or on the value itself for ints and bools, sets `cx_arm` to the arm
+    // that matches it, after setting the names that arm binds. The bodies are
+    // left to `dispatch`, outside the `switch`, so a `break` in one still
+    // leaves the loop around it.
+    fn compile_match<T>(&mut self, value: &Expr, arms: &[Arm<T>]) -> anyhow::Result<String> {
+        let ty = type_of(value, self.types);
+        let mut head = format!(
+            "{} cx_m={};int cx_arm=-1;",
+            c_type(&ty),
+            self.compile_expr(value)?
+        );
+
+        // each arm's test, under the `case` for its constructor, or under
+        // every `case` and the `default` if it has none
+        let mut tests = Vec::new();
+        for (i, arm) in arms.iter().enumerate() {
+            let mut conds = Vec::new();
+            let mut binds = Vec::new();
+            self.destructure(&arm.pattern, &ty, "cx_m", true, &mut conds, &mut binds);
+
+            let mut body = String::new();
+            for (name, path) in binds {
+                head.push_str(&format!(
+                    "{} {};",
+                    self.c_type(&name),
+                    mangle_variable(&name)
+                ));
+                body.push_str(&format!("{}={};", self.variable(&name), path));
+            }
+            let pick = format!("cx_arm={};break;", i);
+            match &arm.guard {
+                Some(guard) => {
+                    body.push_str(&format!("if ({}){{{}}}", self.compile_expr(guard)?, pick))
+                }
+                None => body.push_str(&pick),
+            }
+            let test = if conds.is_empty() {
+                format!("{{{}}}", body)
+            } else {
+                format!("if ({}){{{}}}", conds.join("&&"), body)
+            };
+
+            let label = match (&arm.pattern.kind, &ty) {
+                (PatternKind::Constructor { name, .. }, Type::Enum(_)) => {
+                    Some(format!("k_{}", name))
+                }
+                (PatternKind::Int(n), _) => Some(format!("{}LL", n)),
+                (PatternKind::Bool(b), _) => Some(b.to_string()),
+                _ => None,
+            };
+            tests.push((label, test));
+        }
+
+        let mut labels = Vec::new();
+        for label in tests.iter().filter_map(|(label, _)| label.as_ref()) {
+            if !labels.contains(&label) {
+                labels.push(label);
+            }
+        }
+        let mut cases = String::new();
+        for label in labels.into_iter().map(Some).chain([None]) {
+            match label {
+                Some(label) => cases.push_str(&format!("\tcase {}:", label)),
+                None => cases.push_str("\tdefault:"),
+            }
+            for (_, test) in tests
+                .iter()
+                .filter(|(l, _)| l.is_none() || l.as_ref() == label)
+            {
+                cases.push_str(test);
+            }
+            cases.push_str("break;\n");
+        }
+        let key = match ty {
+            Type::Enum(_) => "cx_m.tag",
+            Type::Int | Type::Bool => "cx_m",
+            _ => "0",
+        };
+        head.push_str(&format!("\n\tswitch ({}){{\n{}\t}}\n", key, cases));
+        Ok(head)
+    }
+
+    // the tests that the value at `path`, a `ty`, matches `pattern`, and the
+    // names it binds with where their values are; the `switch` of a `match`
+    // already tests the value itself, at the `top`
+    fn destructure(
+        &self,
+        pattern: &Pattern,
+        ty: &Type,
+        path: &str,
+        top: bool,
+        conds: &mut Vec<String>,
+        binds: &mut Vec<(String, String)>,
+    ) {
+        match &pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(name) => binds.push((name.clone(), String::from(path))),
+            PatternKind::Int(n) if !top => conds.push(format!("{}=={}LL", path, n)),
+            PatternKind::Bool(true) if !top => conds.push(String::from(path)),
+            PatternKind::Bool(false) if !top => conds.push(format!("!{}", path)),
+            PatternKind::Int(_) | PatternKind::Bool(_) => {}
+            PatternKind::Constructor { name, fields } => {
+                let path = match ty {
+                    Type::Enum(_) => {
+                        if !top {
+                            conds.push(format!("{}.tag==k_{}", path, name));
+                        }
+                        format!("{}.as.v_{}.", path, name)
+                    }
+                    _ => format!("{}.", path),
+                };
+                for (field, (f, ty)) in fields.iter().zip(&self.types.structs[name]) {
+                    let path = format!("{}f_{}", path, f);
+                    self.destructure(field, ty, &path, false, conds, binds);
+                }
+            }
+        }
+    }
+
+    // the designated initializers of a struct or variant, in the order the
+    // fields are written
+    fn fields(&mut self, fields: &[(String, Expr)]) -> anyhow::Result<(String, String)> {
+        let values = fields.iter().map(|(_, value)| value).collect::<Vec<_>>();
+        let (temporaries, values) = self.operands(&values)?;
+        let fields = fields
+            .iter()
+            .zip(values)
+            .map(|((field, _), value)| format!(".f_{}={}", field, value))
+            .collect::<Vec<_>>();
+        Ok((temporaries, fields.join(",")))
+    }
+
+    // the element `array[index]`, which can be assigned to
+    fn compile_index(&mut self, array: &Expr, index: &Expr) -> anyhow::Result<String> {
+        let ty = self.element_type(array);
+        let (temporaries, operands) = self.operands(&[array, index])?;
+        let (a, i) = (&operands[0], &operands[1]);
+        let element = if self.checked {
+            let name = &self.source[array.span.start..array.span.end];
+            format!(
+                "({}*)cx_at({},{},sizeof({0}),{},{})",
+                ty,
+                a,
+                i,
+                c_string(name),
+                self.location(index.span)
+            )
+        } else {
+            format!("(({}*)({})->data+{})", ty, a, i)
+        };
+        // the element's address is what is sequenced, so it can still be
+        // assigned to
+        Ok(format!("(*{})", sequenced(&temporaries, element)))
+    }
+
+    // where `span` is in the source, shown as a diagnostic shows it after its
+    // first line, as a C string
+    fn location(&self, span: Span) -> String {
+        let diagnostic = Diagnostic::new("", span).render(self.filename, self.source);
+        c_string(diagnostic.split_once('\n').unwrap().1)
+    }
+
+    // a function printing arrays of type `ty` as `[1, 2, 3]`, structs as
+    // `Point(x: 1, y: 2)` and enums as their variant in the same way, or its
+    // name alone if it has no fields, after `text`, with what they hold
+    // printed as it would be on its own; it is declared first, as a struct can
+    // hold an array of itself
+    fn printer(&mut self, ty: &Type) -> String {
+        let printer = format!("cx_print_{}", type_name(ty));
+        if !self.generated.insert(printer.clone()) {
+            return printer;
+        }
+
+        let signature = format!("void {}(const char *text, {} v)", printer, c_type(ty));
+        self.prototypes.push(format!("{};\n", signature));
+        let body = match ty {
+            Type::Array(element) => format!(
+                "putchar('[');for (long long i=0;i<v->len;i++){{if (i) fputs(\", \",stdout);{}}}putchar(']');",
+                self.print_one(element, &format!("(({}*)v->data)[i]", c_type(element)))
+            ),
+            Type::Struct(name) => self.print_fields(name, "v."),
+            Type::Enum(name) => {
+                let mut body = String::from("switch (v.tag){");
+                for variant in &self.types.enums[name] {
+                    let fields = self.print_fields(variant, &format!("v.as.v_{}.", variant));
+                    body.push_str(&format!("case k_{}:{}break;", variant, fields));
+                }
+                body.push('}');
+                body
+            }
+            _ => unreachable!("only arrays, structs and enums have printers"),
+        };
+        self.runtime
+            .push(format!("{}{{fputs(text,stdout);{}}}\n", signature, body));
+        printer
+    }
+
+    // statements printing the struct or variant `name` as `Point(x: 1, y: 2)`,
+    // or `Empty` without fields, which are at `path` and their name
+    fn print_fields(&mut self, name: &str, path: &str) -> String {
+        let types = self.types;
+        let fields = &types.structs[name];
+        if fields.is_empty() {
+            return format!("fputs({},stdout);", c_string(name));
+        }
+        let mut body = String::new();
+        for (i, (field, ty)) in fields.iter().enumerate() {
+            let before = if i == 0 {
+                format!("{}(", name)
+            } else {
+                String::from(", ")
+            };
+            body.push_str(&format!(
+                "fputs({},stdout);{}",
+                c_string(&format!("{}{}: ", before, field)),
+                self.print_one(ty, &format!("{}f_{}", path, field))
+            ));
+        }
+        body.push_str("putchar(')');");
+        body
+    }
+
+    // a statement printing `value`, a `ty`, inside another printer
+    fn print_one(&mut self, ty: &Type, value: &str) -> String {
+        match ty {
+            Type::Int => format!("printf(\"%lld\",{});", value),
+            Type::Float => format!("cx_print_number(\"\",{},0);", value),
+            Type::Bool => format!("fputs({}?\"true\":\"false\",stdout);", value),
+            Type::Function(..) => format!("fputs({},stdout);", c_string(&ty.to_string())),
+            Type::Array(_) | Type::Struct(_) | Type::Enum(_) => {
+                format!("{}(\"\",{});", self.printer(ty), value)
+            }
+        }
+    }
+
+    fn compile_expr(&mut self, expr: &Expr) -> anyhow::Result<String> {
+        Ok(match &expr.kind {
+            ExprKind::Int(n) => format!("{}LL", n),
+            // `{:?}` always writes a `.` or an exponent, so C reads a double
+            ExprKind::Float(n) => format!("{:?}", n),
+            ExprKind::Bool(b) => b.to_string(),
+            ExprKind::Str(_) => unreachable!("the resolver only allows strings in `print`"),
+            ExprKind::Ident(ident) => self.variable(ident),
+            ExprKind::Call { name, args } => {
                 let ident_key = self
                     .rodeo
                     .get(name)
-                    .ok_or(anyhow::anyhow!("function must be defined"))?;
-                format!(
-                    "{}({})",
-                    self.ref_env.get(&ident_key).unwrap().clone(),
-                    args.into_iter()
-                        .map(|a| self.compile_expr(&a))
-                        .collect::<Result<Vec<_>, anyhow::Error>>()?
-                        .join(",")
+                    .ok_or_else(|| Diagnostic::new("function must be defined", expr.span))?;
+                let (temporaries, mut args) = self.operands(&args.iter().collect::<Vec<_>>())?;
+
+                let f = &self.ref_env[&ident_key];
+
+                if !f.captures.is_empty() {
+                    let env = f
+                        .captures
+                        .iter()
+                        .map(|c| {
+                            if self.captured.contains(c) {
+                                format!("env->{}", mangle_variable(c))
+                            } else {
+                                format!("&{}", mangle_variable(c))
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    args.insert(0, format!("&(struct env_{}){{{}}}", f.name, env.join(",")));
+                }
+
+                sequenced(&temporaries, format!("{}({})", f.name, args.join(",")))
+            }
+            ExprKind::Function(name) => self.function_value(name),
+            ExprKind::Lambda { name, body, .. } => {
+                let f = self.compile_program(body, Some(name))?;
+                self.functions.push(f);
+                self.function_value(name)
+            }
+            ExprKind::CallValue { callee, args } => {
+                let Type::Function(params, returns) = type_of(callee, self.types) else {
+                    unreachable!("the checker only allows calls through functions")
+                };
+                let operands = std::iter::once(&**callee).chain(args).collect::<Vec<_>>();
+                let (mut temporaries, compiled) = self.operands(&operands)?;
+                // ints given for float parameters are converted by the cast
+                let pointer = std::iter::once(String::from("void*"))
+                    .chain(params.iter().map(c_type))
+                    .collect::<Vec<_>>();
+                temporaries.push_str(&format!("cx_fn cx_f={};", compiled[0]));
+                let args = std::iter::once(String::from("cx_f.env"))
+                    .chain(compiled[1..].iter().cloned())
+                    .collect::<Vec<_>>();
+                sequenced(
+                    &temporaries,
+                    format!(
+                        "(({}(*)({}))cx_f.fn)({})",
+                        c_type(&returns),
+                        pointer.join(","),
+                        args.join(",")
+                    ),
                 )
             }
-            Expr::Add(ref lhs, ref rhs) => {
-                format!("({}+{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Add(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}+{})", a, b))?,
+            ExprKind::Sub(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}-{})", a, b))?,
+            ExprKind::Mul(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}*{})", a, b))?,
+            ExprKind::Div(lhs, rhs) if self.is_int(expr) => {
+                let location = self.location(expr.span);
+                self.binary(lhs, rhs, |a, b| format!("cx_div({},{},{})", a, b, location))?
             }
-            Expr::Sub(ref lhs, ref rhs) => {
-                format!("({}-{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Div(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}/{})", a, b))?,
+            ExprKind::Pow(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("pow({},{})", a, b))?,
+            ExprKind::Mod(lhs, rhs) if self.is_int(expr) => {
+                let location = self.location(expr.span);
+                self.binary(lhs, rhs, |a, b| format!("cx_mod({},{},{})", a, b, location))?
             }
-            Expr::Mul(ref lhs, ref rhs) => {
-                format!("({}*{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Mod(lhs, rhs) => {
+                self.binary(lhs, rhs, |a, b| format!("fmod({},{})", a, b))?
             }
-            Expr::Div(ref lhs, ref rhs) => {
-                format!("({}/{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Leq(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}<={})", a, b))?,
+            ExprKind::Geq(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}>={})", a, b))?,
+            ExprKind::Lt(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}<{})", a, b))?,
+            ExprKind::Gt(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}>{})", a, b))?,
+            ExprKind::Eq(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}=={})", a, b))?,
+            ExprKind::Neq(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("({}!={})", a, b))?,
+            ExprKind::And(ref lhs, ref rhs) => {
+                format!("({}&&{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
             }
-            Expr::Pow(ref lhs, ref rhs) => format!(
-                "pow({},{})",
-                self.compile_expr(lhs)?,
-                self.compile_expr(rhs)?
-            ),
-            Expr::Mod(ref lhs, ref rhs) => format!("((int){}%(int){})", self.compile_expr(lhs)?, self.compile_expr(rhs)?),
-            Expr::Leq(ref lhs, ref rhs) => {
-                format!("({}<={})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Or(ref lhs, ref rhs) => {
+                format!("({}||{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
             }
-            Expr::Geq(ref lhs, ref rhs) => {
-                format!("({}>={})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Xor(lhs, rhs) => self.binary(lhs, rhs, |a, b| format!("(!{}!=!{})", a, b))?,
+            ExprKind::Neg(ref e) => format!("(-{})", self.compile_expr(e)?),
+            ExprKind::Pos(ref e) => format!("(+{})", self.compile_expr(e)?),
+            ExprKind::Not(ref e) => format!("(!{})", self.compile_expr(e)?),
+            ExprKind::Array(elements) if elements.is_empty() => {
+                format!("cx_array_new(0,sizeof({}))", self.element_type(expr))
             }
-            Expr::Lt(ref lhs, ref rhs) => {
-                format!("({}<{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Array(elements) => {
+                let ty = self.element_type(expr);
+                let (temporaries, elements) =
+                    self.operands(&elements.iter().collect::<Vec<_>>())?;
+                let array = format!(
+                    "cx_array_of({},sizeof({}),({1}[]){{{}}})",
+                    elements.len(),
+                    ty,
+                    elements.join(",")
+                );
+                sequenced(&temporaries, array)
             }
-            Expr::Gt(ref lhs, ref rhs) => {
-                format!("({}>{})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::NewArray { len, fill: None } => format!(
+                "cx_array_new(cx_length({},{}),sizeof({}))",
+                self.compile_expr(len)?,
+                self.location(expr.span),
+                self.element_type(expr)
+            ),
+            ExprKind::NewArray {
+                len,
+                fill: Some(fill),
+            } => {
+                let ty = self.element_type(expr);
+                let (temporaries, operands) = self.operands(&[len, fill])?;
+                let array = format!(
+                    "cx_array_fill({},sizeof({}),({1}[]){{{}}},{})",
+                    operands[0],
+                    ty,
+                    operands[1],
+                    self.location(expr.span)
+                );
+                sequenced(&temporaries, array)
+            }
+            ExprKind::Index { array, index } => self.compile_index(array, index)?,
+            ExprKind::Len(array) => format!("({})->len", self.compile_expr(array)?),
+            ExprKind::Struct { name, fields } => {
+                let (temporaries, fields) = self.fields(fields)?;
+                sequenced(&temporaries, format!("((struct s_{}){{{}}})", name, fields))
             }
-            Expr::Eq(ref lhs, ref rhs) => {
-                format!("({}=={})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Field { expr, name } => format!("({}).f_{}", self.compile_expr(expr)?, name),
+            ExprKind::Variant { ty, name, fields } if fields.is_empty() => {
+                format!("((struct e_{}){{.tag=k_{}}})", ty, name)
             }
-            Expr::Neq(ref lhs, ref rhs) => {
-                format!("({}!={})", self.compile_expr(lhs)?, self.compile_expr(rhs)?)
+            ExprKind::Variant { ty, name, fields } => {
+                let (temporaries, fields) = self.fields(fields)?;
+                let variant = format!(
+                    "((struct e_{}){{.tag=k_{},.as.v_{1}={{{}}}}})",
+                    ty, name, fields
+                );
+                sequenced(&temporaries, variant)
             }
+            // a GNU statement expression, as the arms are picked by statements
+            ExprKind::Match { value, arms } => {
+                let head = self.compile_match(value, arms)?;
+                let mut bodies = Vec::new();
+                for arm in arms {
+                    bodies.push(format!("cx_r={};", self.compile_expr(&arm.body)?));
+                }
+                format!(
+                    "({{{} cx_r;{}{}cx_r;}})",
+                    c_type(&type_of(expr, self.types)),
+                    head,
+                    dispatch(&bodies)
+                )
+            }
+            ExprKind::Record(_) => unreachable!("the resolver makes records into structs"),
         })
     }
 }
+
+// `expr` after the declarations of the temporaries it uses, if there are any
+fn sequenced(temporaries: &str, expr: String) -> String {
+    if temporaries.is_empty() {
+        expr
+    } else {
+        format!("({{{}{};}})", temporaries, expr)
+    }
+}
+
+// runs the body of the arm `Compiler::compile_match` picked
+fn dispatch(bodies: &[String]) -> String {
+    let mut code = String::from("\t");
+    for (i, body) in bodies.iter().enumerate() {
+        if i + 1 < bodies.len() {
+            code.push_str(&format!("if (cx_arm=={}){} else ", i, body));
+        } else {
+            code.push_str(body);
+        }
+    }
+    code.push('\n');
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::Checker;
+    use crate::interpreter::Interpreter;
+    use crate::parser::parse;
+    use crate::resolver::Resolver;
+
+    // what `source` prints when it is compiled and run, and when it is
+    // interpreted, each with the error that stopped it, if any
+    fn both(source: &str, name: &str) -> ((String, String), (String, String)) {
+        let (mut program, _) = parse(source).expect("parses");
+        Resolver::resolve(&mut program, source).expect("resolves");
+        let types = Checker::check(&program, source).expect("checks");
+
+        let c = Compiler::generate(&program, &types, source, name, true).expect("compiles");
+        let binary = std::env::temp_dir().join(format!("cx-{}-{}", name, std::process::id()));
+        let mut gcc = Command::new("gcc")
+            .arg("-o")
+            .arg(&binary)
+            .args(GCC_FLAGS)
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("could not start gcc");
+        gcc.stdin.take().unwrap().write_all(c.as_bytes()).unwrap();
+        let gcc = gcc.wait_with_output().unwrap();
+        assert!(
+            gcc.status.success(),
+            "gcc fails on {}: {}",
+            name,
+            String::from_utf8_lossy(&gcc.stderr)
+        );
+        let output = Command::new(&binary).output().unwrap();
+        std::fs::remove_file(&binary).unwrap();
+        let compiled = (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        );
+
+        let mut out = Vec::new();
+        let result = Interpreter::run_with(&program, &types, source, &mut out);
+        let error = match result {
+            Ok(()) => String::new(),
+            Err(e) => match e.downcast_ref::<Diagnostic>() {
+                Some(diagnostic) => diagnostic.render(name, source),
+                None => e.to_string(),
+            },
+        };
+        let interpreted = (String::from_utf8(out).unwrap(), error);
+
+        (compiled, interpreted)
+    }
+
+    const PROGRAMS: &[(&str, &str)] = &[
+        (
+            "numbers",
+            "print(9223372036854775807 + 1, -7 / 2, -7 % 2, 7.0 / 2, 2 ^ 0.5, 1.0 / 0)\n\
+             print(\"{3.14159:8.2}|{42:4}|{1500.0:.1e}|{-0.0}\")\n\
+             local x = 0.1 + 0.2\n\
+             print(x, x == 0.3, 2.0 ^ 2000)\n\
+             local y = 2.0\n\
+             print(-(y ^ 63), y ^ 63)",
+        ),
+        (
+            "ranges",
+            "for t in 0..1 step 0.1 do\nprint(\"{t:.17}\")\nend\n\
+             for i in 10..=0 step -3 do\nprint(i)\nend\n\
+             for t in 1..=0 step -0.25 do\nprint(t)\nend",
+        ),
+        (
+            "functions",
+            "counter() do\nlocal n = 0\nstep() do\nn = n + 1\nn\nend\nstep\nend\n\
+             local c = counter()\nprint(c(), c(), c())\n\
+             compose(f: fn(float) -> float, g: fn(float) -> float) = fn(x) => f(g(x))\n\
+             local h = compose(fn(x) => x * 2, fn(x) => x + 0.5)\nprint(h(1))\n\
+             fib(n) do\nif n < 2 do\nreturn n\nend\nfib(n - 1) + fib(n - 2)\nend\nprint(fib(20))",
+        ),
+        (
+            "values",
+            "struct P do x, y: float end\nenum S do C(r), Sq(p: P), N end\n\
+             local a = [P(1, 2), {x: 3, y: 4.5}]\nlocal b = a\nb[0].x = 7\n\
+             local p = a[1]\np.y = 0\nprint(a, p)\n\
+             area(s) = match s do C(r) => r * r, Sq(P(x, y)) => x * y, N => 0 end\n\
+             print(area(C(3)), area(Sq(P(2, 1.5))), area(N), Sq(P(1, 1)), N)\n\
+             local grid = array(2, array(2, 0))\ngrid[0][1] = 5\npush(grid, [1])\nprint(grid, len(grid))",
+        ),
+        (
+            "order",
+            "f(a, b, c) = a * 100 + b * 10 + c\n\
+             main() do\nlocal n = 0\nnext() do\nn = n + 1\nn\nend\n\
+             print(f(next(), next(), next()), next() - next(), [next(), next()])\nn\nend\n\
+             main()",
+        ),
+        (
+            "bounds",
+            "local a = [1, 2]\nprint(\"start\")\nprint(\"index\", a[2])",
+        ),
+        (
+            "division",
+            "print(\"start\")\nlocal z = 0\nprint(\"before\", 10 % z)",
+        ),
+        (
+            "length",
+            "print(\"start\")\nlocal n = -3\nlocal a = array(n, 0.5)",
+        ),
+        (
+            "step",
+            "local s = 0\nfor i in 0..3 step s do\nprint(i)\nend",
+        ),
+    ];
+
+    #[test]
+    fn backends_agree() {
+        for (name, source) in PROGRAMS {
+            let (compiled, interpreted) = both(source, name);
+            assert_eq!(compiled, interpreted, "the backends disagree on {}", name);
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::types::Span;
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            label: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn render(&self, filename: &str, source: &str) -> String {
+        let (line, col) = line_col(source, self.span.start);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let gutter = " ".repeat(line.to_string().len());
+
+        // only the first line of a multi-line span is underlined
+        let line_end = text.len() + 1 - col;
+        let width = self
+            .span
+            .end
+            .saturating_sub(self.span.start)
+            .clamp(1, line_end.max(1));
+
+        let mut out = format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter,
+            filename,
+            line,
+            col,
+            gutter,
+            line,
+            text,
+            gutter,
+            " ".repeat(text[..col - 1].chars().count()),
+            "^".repeat(width),
+        );
+
+        if let Some(label) = &self.label {
+            out.push(' ');
+            out.push_str(label);
+        }
+        out.push('\n');
+
+        if !self.notes.is_empty() {
+            out.push_str(&format!("{} |\n", gutter));
+        }
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+// 1-based line and column of a byte offset
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    (before.matches('\n').count() + 1, offset - line_start + 1)
+}
+
+// the closest `do`, `end` or `,` before `offset`, which is usually the block
+// or list the parser was in the middle of when it gave up; anything inside
+// `comments` is skipped
+pub fn nearest_keyword(
+    source: &str,
+    offset: usize,
+    comments: &[Span],
+) -> Option<(&'static str, usize)> {
+    let before = &source[..offset.min(source.len())];
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    for (i, c) in before.char_indices().rev() {
+        if comments.iter().any(|c| c.start <= i && i < c.end) {
+            continue;
+        }
+
+        if c == ',' {
+            return Some((",", i));
+        }
+
+        let end = i + c.len_utf8();
+        for keyword in ["do", "end"] {
+            if before[..end].ends_with(keyword) {
+                let start = end - keyword.len();
+                let bounded_left = !before[..start].ends_with(is_word);
+                let bounded_right = !source[end..].starts_with(is_word);
+                if bounded_left && bounded_right {
+                    return Some((keyword, start));
+                }
+            }
+        }
+    }
+
+    None
+}
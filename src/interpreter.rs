@@ -0,0 +1,1281 @@
+use crate::checker::{type_of, Types};
+use crate::diagnostics::Diagnostic;
+use crate::resolver::describe_function;
+use crate::types::*;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{stdout, BufWriter, Write};
+use std::rc::Rc;
+
+// Values follow the C the compiler emits: ints are `long long`, floats are
+// `double` and bools are `bool`, with the types given by the checker, so
+// arithmetic and printing match the compiled binary exactly. A function
+// value is the index of its closure, which a function that captures nothing
+// shares between all of its values. An array is the index of its elements,
+// which are shared like the pointer to them in C. A struct holds its fields,
+// which its copies share until one of them has a field assigned to and takes
+// fields of its own, so it acts as a C struct copied wherever it is stored.
+// An enum is the index of its variant and what the variant holds, which is
+// shared the same way.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+    Array(usize),
+    Struct(Rc<[Value]>),
+    Enum(usize, Rc<[Value]>),
+    Function(usize),
+}
+
+impl Value {
+    fn as_double(&self) -> f64 {
+        match *self {
+            Value::Int(i) => i as f64,
+            Value::Double(d) => d,
+            Value::Bool(b) => b as i64 as f64,
+            Value::Array(_) | Value::Struct(_) | Value::Enum(..) | Value::Function(_) => {
+                unreachable!("the checker keeps arrays, structs and functions out of arithmetic")
+            }
+        }
+    }
+
+    fn as_int(&self) -> i64 {
+        match *self {
+            Value::Int(i) => i,
+            Value::Double(d) => d as i64,
+            Value::Bool(b) => b as i64,
+            Value::Array(_) | Value::Struct(_) | Value::Enum(..) | Value::Function(_) => {
+                unreachable!("the checker keeps arrays, structs and functions out of arithmetic")
+            }
+        }
+    }
+
+    fn truthy(&self) -> bool {
+        match *self {
+            Value::Int(i) => i != 0,
+            Value::Double(d) => d != 0.0,
+            Value::Bool(b) => b,
+            Value::Array(_) | Value::Struct(_) | Value::Enum(..) | Value::Function(_) => {
+                unreachable!("the checker keeps arrays, structs and functions out of conditions")
+            }
+        }
+    }
+
+    fn of_type(self, ty: &Type) -> Value {
+        match ty {
+            Type::Int => Value::Int(self.as_int()),
+            Type::Float => Value::Double(self.as_double()),
+            Type::Bool => Value::Bool(self.truthy()),
+            Type::Array(_) | Type::Struct(_) | Type::Enum(_) | Type::Function(..) => self,
+        }
+    }
+
+    // keeps the C type of `self`, as an assignment to a declared variable does
+    fn convert(&self, value: Value) -> Value {
+        match self {
+            Value::Int(_) => Value::Int(value.as_int()),
+            Value::Double(_) => Value::Double(value.as_double()),
+            Value::Bool(_) => Value::Bool(value.truthy()),
+            Value::Array(_) | Value::Struct(_) | Value::Enum(..) | Value::Function(_) => value,
+        }
+    }
+}
+
+// `value` the way `printf` writes it with the conversion `print` picks for
+// `format`, padded to its width
+fn format_with(value: Value, format: Format) -> String {
+    let n = value.as_double();
+    let s = match (format.precision, format.scientific) {
+        (None, false) => format_value(value),
+        _ if !n.is_finite() => format_number(n),
+        (precision, true) => {
+            // C always gives the exponent a sign and at least two digits
+            let s = format!("{:.*e}", precision.unwrap_or(6), n);
+            let (mantissa, exponent) = s.split_once('e').unwrap();
+            let exponent: i32 = exponent.parse().unwrap();
+            format!(
+                "{}e{}{:02}",
+                mantissa,
+                if exponent < 0 { '-' } else { '+' },
+                exponent.abs()
+            )
+        }
+        (Some(precision), false) => format!("{:.*}", precision, n),
+    };
+    pad(s, format.width)
+}
+
+// `printf` pads to a number of bytes, not characters
+fn pad(s: String, width: usize) -> String {
+    match width.checked_sub(s.len()) {
+        Some(padding) if padding > 0 => format!("{}{}", " ".repeat(padding), s),
+        _ => s,
+    }
+}
+
+fn format_value(value: Value) -> String {
+    match value {
+        Value::Int(i) => i.to_string(),
+        Value::Double(d) => format_number(d),
+        Value::Bool(b) => b.to_string(),
+        Value::Array(_) | Value::Struct(_) | Value::Enum(..) | Value::Function(_) => {
+            unreachable!(
+                "arrays, structs, enums and functions are printed with `Interpreter::show`"
+            )
+        }
+    }
+}
+
+// whole numbers in the range of a `long long` print as one, as
+// `cx_print_number` tests with `n==(long long)n`
+fn format_number(n: f64) -> String {
+    if (-9223372036854775808.0..9223372036854775808.0).contains(&n) && n == n.trunc() {
+        format!("{}", n as i64)
+    } else if n.is_nan() {
+        String::from(if n.is_sign_negative() { "-nan" } else { "nan" })
+    } else if n.is_infinite() {
+        String::from(if n < 0.0 { "-inf" } else { "inf" })
+    } else {
+        format!("{:.6}", n)
+    }
+}
+
+// the interpreter recurses as deeply as the program does, so it runs on a
+// thread with a stack of `STACK` bytes, and a call that would leave less than
+// `MARGIN` of it for its body stops the program with an error instead
+const STACK: usize = 1 << 30;
+const MARGIN: usize = 1 << 20;
+
+// roughly how far down the stack the caller is, as it grows down
+fn stack_address() -> usize {
+    let marker = 0u8;
+    std::hint::black_box(&marker) as *const u8 as usize
+}
+
+struct Function<'a> {
+    args: &'a [Param],
+    body: &'a Program,
+    captures: &'a [String],
+}
+
+// how a statement finished
+enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
+}
+
+// a function value, with its own copies of the variables it captures
+struct Closure<'a> {
+    function: &'a str,
+    env: HashMap<&'a str, Rc<RefCell<Value>>>,
+}
+
+pub struct Interpreter<'a, W: Write> {
+    functions: HashMap<&'a str, Function<'a>>,
+    closures: Vec<Closure<'a>>,
+    // the closures of functions that capture nothing, by their name
+    shared: HashMap<&'a str, usize>,
+    // variables are shared cells so functions can capture them by reference
+    scopes: Vec<HashMap<&'a str, Rc<RefCell<Value>>>>,
+    arrays: Vec<Vec<Value>>,
+    types: &'a Types,
+    source: &'a str,
+    out: W,
+    // the lowest the stack can go before a call
+    limit: usize,
+}
+
+impl<'a> Interpreter<'a, BufWriter<std::io::Stdout>> {
+    pub fn run(prog: &'a Program, types: &'a Types, source: &'a str) -> anyhow::Result<()> {
+        Interpreter::run_with(prog, types, source, BufWriter::new(stdout()))
+    }
+}
+
+impl<'a, W: Write + Send> Interpreter<'a, W> {
+    // runs `prog` on a thread of its own, writing what it prints to `out`
+    pub fn run_with(
+        prog: &'a Program,
+        types: &'a Types,
+        source: &'a str,
+        out: W,
+    ) -> anyhow::Result<()> {
+        std::thread::scope(|scope| {
+            let thread =
+                std::thread::Builder::new()
+                    .stack_size(STACK)
+                    .spawn_scoped(scope, move || {
+                        let mut interpreter = Interpreter::new(types, source, out);
+
+                        let result = interpreter.run_program(prog);
+
+                        interpreter.out.flush()?;
+
+                        result
+                    })?;
+            thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    // has to be called at the top of a thread with a stack of `STACK`
+    fn new(types: &'a Types, source: &'a str, out: W) -> Self {
+        Self {
+            functions: HashMap::new(),
+            closures: Vec::new(),
+            shared: HashMap::new(),
+            scopes: Vec::new(),
+            arrays: Vec::new(),
+            types,
+            source,
+            out,
+            limit: stack_address() - (STACK - MARGIN),
+        }
+    }
+
+    fn run_program(&mut self, prog: &'a Program) -> anyhow::Result<()> {
+        self.collect_functions(prog)?;
+
+        self.scopes.push(HashMap::new());
+        self.exec_body(prog, false)?;
+        self.scopes.pop();
+
+        Ok(())
+    }
+
+    // the resolver gives every function a unique name, so they can all be
+    // registered up front however deeply they are nested
+    fn collect_functions(&mut self, body: &'a Program) -> anyhow::Result<()> {
+        for stmt in body {
+            if let StmtKind::FunctionDefinition {
+                name,
+                args,
+                body,
+                captures,
+                ..
+            } = &stmt.kind
+            {
+                if self
+                    .functions
+                    .insert(
+                        name,
+                        Function {
+                            args,
+                            body,
+                            captures,
+                        },
+                    )
+                    .is_some()
+                {
+                    return Err(Diagnostic::new(
+                        format!("function {} already exists", name),
+                        stmt.span,
+                    )
+                    .into());
+                }
+            }
+            for block in stmt.blocks() {
+                self.collect_functions(block)?;
+            }
+            for expr in stmt.exprs() {
+                self.collect_lambdas(expr)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_lambdas(&mut self, expr: &'a Expr) -> anyhow::Result<()> {
+        if let ExprKind::Lambda {
+            name,
+            args,
+            body,
+            captures,
+            ..
+        } = &expr.kind
+        {
+            self.functions.insert(
+                name,
+                Function {
+                    args,
+                    body,
+                    captures,
+                },
+            );
+            self.collect_functions(body)?;
+        }
+        for child in expr.children() {
+            self.collect_lambdas(child)?;
+        }
+
+        Ok(())
+    }
+
+    fn exec_block(&mut self, body: &'a Program, tail: bool) -> anyhow::Result<Flow> {
+        self.scopes.push(HashMap::new());
+        let result = self.exec_body(body, tail);
+        self.scopes.pop();
+        result
+    }
+
+    // `tail` blocks end a function body, see `Compiler::compile_body`
+    fn exec_body(&mut self, body: &'a Program, tail: bool) -> anyhow::Result<Flow> {
+        let count = body.len();
+
+        for (i, stmt) in body.iter().enumerate() {
+            let tail = tail && i == count - 1;
+            match &stmt.kind {
+                StmtKind::Expression(expr) => {
+                    if tail {
+                        return Ok(Flow::Return(self.eval(expr)?));
+                    }
+                    let value = self.print_value(expr, Format::default())?;
+                    writeln!(self.out, "{}", value)?;
+                }
+                StmtKind::Print(args) => {
+                    // text is held back until the next value has been
+                    // evaluated, as the compiled `printf`s do, so output from
+                    // the values and a runtime error part way through a line
+                    // come out the same
+                    let mut text = String::new();
+                    for (i, (arg, format)) in args.iter().enumerate() {
+                        if i > 0 {
+                            text.push(' ');
+                        }
+                        let segments = match &arg.kind {
+                            ExprKind::Str(segments) => segments,
+                            _ => {
+                                let value = self.print_value(arg, *format)?;
+                                write!(self.out, "{}{}", text, value)?;
+                                text.clear();
+                                continue;
+                            }
+                        };
+                        // only strings without interpolations have a width
+                        let mut pending = String::new();
+                        for segment in segments {
+                            match segment {
+                                Segment::Text(s) => pending.push_str(s),
+                                Segment::Value(expr, format) => {
+                                    let value = self.print_value(expr, *format)?;
+                                    write!(self.out, "{}{}{}", text, pending, value)?;
+                                    text.clear();
+                                    pending.clear();
+                                }
+                            }
+                        }
+                        text.push_str(&pad(pending, format.width));
+                    }
+                    writeln!(self.out, "{}", text)?;
+                }
+                StmtKind::FunctionDefinition { .. } => {}
+                StmtKind::Declaration { name, value } => {
+                    let value = self.eval(value)?.of_type(&self.types.variable(name));
+                    self.scopes
+                        .last_mut()
+                        .unwrap()
+                        .insert(name, Rc::new(RefCell::new(value)));
+                }
+                StmtKind::Assignment { name, value } => {
+                    let value = self.eval(value)?;
+                    let slot = self.lookup(name, stmt.span)?;
+                    let value = slot.borrow().convert(value);
+                    slot.replace(value);
+                }
+                StmtKind::IfStatement { cond, body, branch } => {
+                    let flow = if self.eval(cond)?.truthy() {
+                        self.exec_block(body, tail)?
+                    } else if let Some(b) = branch {
+                        self.exec_block(b, tail)?
+                    } else {
+                        Flow::Normal
+                    };
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                StmtKind::For { body, ident, exprs } => {
+                    if let Some(value) = self.exec_for(body, ident, exprs, stmt.span)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                StmtKind::ForRange {
+                    body,
+                    ident,
+                    start,
+                    stop,
+                    inclusive,
+                    step,
+                } => {
+                    let range = (&**start, &**stop, *inclusive, step.as_deref());
+                    if let Some(value) = self.exec_range(body, ident, range, stmt.span)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                StmtKind::ForEach { body, ident, array } => {
+                    if let Some(value) = self.exec_each(body, ident, array)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+                StmtKind::While { body, expr } => {
+                    while self.eval(expr)?.truthy() {
+                        match self.exec_block(body, false)? {
+                            Flow::Return(value) => return Ok(Flow::Return(value)),
+                            Flow::Break => break,
+                            Flow::Normal | Flow::Continue => {}
+                        }
+                    }
+                }
+                StmtKind::Return(expr) => return Ok(Flow::Return(self.eval(expr)?)),
+                // the value is evaluated before the array, as in the
+                // compiled code
+                StmtKind::IndexAssignment {
+                    array,
+                    index,
+                    value,
+                } => {
+                    let value = self.eval(value)?.of_type(&element_type(array, self.types));
+                    let (a, i) = self.index(array, index)?;
+                    self.arrays[a][i] = value;
+                }
+                StmtKind::Push { array, value } => {
+                    let value = self.eval(value)?.of_type(&element_type(array, self.types));
+                    match self.eval(array)? {
+                        Value::Array(a) => self.arrays[a].push(value),
+                        _ => unreachable!("the checker only allows pushing to arrays"),
+                    }
+                }
+                // like an element, the value first, and then the struct whose
+                // field it is, which is the one stored where `target` is
+                StmtKind::FieldAssignment {
+                    target,
+                    field,
+                    value,
+                } => {
+                    let value = self.eval(value)?;
+                    let mut path = Vec::new();
+                    let place = self.place(target, &mut path)?;
+                    let i = self.field(target, field);
+                    path.push(i);
+                    let value = match type_of(target, self.types) {
+                        Type::Struct(name) => value.of_type(&self.types.structs[&name][i].1),
+                        _ => unreachable!("the checker only allows fields of structs"),
+                    };
+                    match place {
+                        Place::Variable(slot) => assign(&mut slot.borrow_mut(), &path, value),
+                        Place::Element(a, i) => assign(&mut self.arrays[a][i], &path, value),
+                    }
+                }
+                StmtKind::Match { value, arms } => {
+                    let arm = self.choose(value, arms)?;
+                    let flow = self.exec_body(&arm.body, tail);
+                    self.scopes.pop();
+                    let flow = flow?;
+                    if !matches!(flow, Flow::Normal) {
+                        return Ok(flow);
+                    }
+                }
+                StmtKind::Struct { .. } | StmtKind::Enum { .. } => {}
+                StmtKind::Break => return Ok(Flow::Break),
+                StmtKind::Continue => return Ok(Flow::Continue),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    // the value of a `return` from inside the loop, if there was one
+    fn exec_for(
+        &mut self,
+        body: &'a Program,
+        ident: &'a str,
+        exprs: &'a [Expr],
+        span: Span,
+    ) -> anyhow::Result<Option<Value>> {
+        // the counter's type, which is what C converts the bounds to
+        let ty = self.types.variable(ident);
+        let start = match exprs.len() {
+            1 => Value::Int(0),
+            _ => self.eval(&exprs[0])?,
+        };
+
+        self.scopes.push(HashMap::from([(
+            ident,
+            Rc::new(RefCell::new(start.of_type(&ty))),
+        )]));
+
+        let result = (|| {
+            loop {
+                let i = self.lookup(ident, span)?.borrow().clone();
+
+                // start>stop?i>stop:i<stop, re-evaluated on every iteration
+                let cond = match exprs.len() {
+                    1 => compare(&i, &self.eval(&exprs[0])?).is_some_and(Ordering::is_lt),
+                    _ => {
+                        if self.descending(exprs)? {
+                            compare(&i, &self.eval(&exprs[1])?).is_some_and(Ordering::is_gt)
+                        } else {
+                            compare(&i, &self.eval(&exprs[1])?).is_some_and(Ordering::is_lt)
+                        }
+                    }
+                };
+
+                if !cond {
+                    return Ok(None);
+                }
+
+                // `continue` still steps the counter, as in C
+                match self.exec_block(body, false)? {
+                    Flow::Return(value) => return Ok(Some(value)),
+                    Flow::Break => return Ok(None),
+                    Flow::Normal | Flow::Continue => {}
+                }
+
+                let step = match exprs.len() {
+                    1 => Value::Int(1),
+                    2 => {
+                        if self.descending(exprs)? {
+                            Value::Int(-1)
+                        } else {
+                            Value::Int(1)
+                        }
+                    }
+                    _ => self.eval(&exprs[2])?,
+                };
+
+                let slot = self.lookup(ident, span)?;
+                let i = match (slot.borrow().clone(), step) {
+                    (Value::Int(i), Value::Int(step)) => Value::Int(i.wrapping_add(step)),
+                    (i, step) => Value::Double(i.as_double() + step.as_double()),
+                };
+                slot.replace(i.of_type(&ty));
+            }
+        })();
+
+        self.scopes.pop();
+
+        result
+    }
+
+    // like `exec_for`, but with the bounds and step evaluated once, and
+    // counting down only with a negative step; a float counter is worked
+    // out afresh each time from how many steps it has taken
+    fn exec_range(
+        &mut self,
+        body: &'a Program,
+        ident: &'a str,
+        (start, stop, inclusive, step): (&'a Expr, &'a Expr, bool, Option<&'a Expr>),
+        span: Span,
+    ) -> anyhow::Result<Option<Value>> {
+        let ty = self.types.variable(ident);
+        let start = self.eval(start)?.of_type(&ty);
+        let stop = self.eval(stop)?.of_type(&ty);
+        let step = match step {
+            Some(step) => {
+                let value = self.eval(step)?.of_type(&ty);
+                if compare(&value, &Value::Int(0)) == Some(Ordering::Equal) {
+                    return Err(Diagnostic::new("a range can't have a step of 0", step.span).into());
+                }
+                value
+            }
+            None => Value::Int(1).of_type(&ty),
+        };
+        if ty == Type::Float {
+            let (start, step) = (start.as_double(), step.as_double());
+            let count = range_count(start, stop.as_double(), step, inclusive);
+            let mut k = 0.0;
+            while k < count {
+                let i = Value::Double(start + k * step);
+                self.scopes
+                    .push(HashMap::from([(ident, Rc::new(RefCell::new(i)))]));
+                let flow = self.exec_block(body, false);
+                self.scopes.pop();
+                match flow? {
+                    Flow::Return(value) => return Ok(Some(value)),
+                    Flow::Break => return Ok(None),
+                    Flow::Normal | Flow::Continue => {}
+                }
+                k += 1.0;
+            }
+            return Ok(None);
+        }
+
+        let descending = compare(&step, &Value::Int(0)) != Some(Ordering::Greater);
+
+        self.scopes
+            .push(HashMap::from([(ident, Rc::new(RefCell::new(start)))]));
+
+        let result = (|| loop {
+            let i = self.lookup(ident, span)?.borrow().clone();
+            let cond = match (compare(&i, &stop), descending, inclusive) {
+                (Some(Ordering::Less), false, _) | (Some(Ordering::Greater), true, _) => true,
+                (Some(Ordering::Equal), _, inclusive) => inclusive,
+                _ => false,
+            };
+            if !cond {
+                return Ok(None);
+            }
+
+            match self.exec_block(body, false)? {
+                Flow::Return(value) => return Ok(Some(value)),
+                Flow::Break => return Ok(None),
+                Flow::Normal | Flow::Continue => {}
+            }
+
+            let slot = self.lookup(ident, span)?;
+            let i = match (slot.borrow().clone(), &step) {
+                (Value::Int(i), &Value::Int(step)) => Value::Int(i.wrapping_add(step)),
+                (i, step) => Value::Double(i.as_double() + step.as_double()),
+            };
+            slot.replace(i.of_type(&ty));
+        })();
+
+        self.scopes.pop();
+
+        result
+    }
+
+    // every element of `array`, including those pushed to it by the loop
+    fn exec_each(
+        &mut self,
+        body: &'a Program,
+        ident: &'a str,
+        array: &'a Expr,
+    ) -> anyhow::Result<Option<Value>> {
+        let ty = self.types.variable(ident);
+        let a = match self.eval(array)? {
+            Value::Array(a) => a,
+            _ => unreachable!("the checker only allows `for ... in` over arrays"),
+        };
+
+        let mut i = 0;
+        while i < self.arrays[a].len() {
+            let value = self.arrays[a][i].clone().of_type(&ty);
+            self.scopes
+                .push(HashMap::from([(ident, Rc::new(RefCell::new(value)))]));
+            let flow = self.exec_block(body, false);
+            self.scopes.pop();
+            match flow? {
+                Flow::Return(value) => return Ok(Some(value)),
+                Flow::Break => return Ok(None),
+                Flow::Normal | Flow::Continue => {}
+            }
+            i += 1;
+        }
+
+        Ok(None)
+    }
+
+    fn descending(&mut self, exprs: &'a [Expr]) -> anyhow::Result<bool> {
+        let start = self.eval(&exprs[0])?;
+        let stop = self.eval(&exprs[1])?;
+        Ok(compare(&start, &stop).is_some_and(Ordering::is_gt))
+    }
+
+    fn lookup(&self, name: &str, span: Span) -> anyhow::Result<Rc<RefCell<Value>>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+            .ok_or_else(|| {
+                Diagnostic::new(format!("variable {} is not defined", name), span).into()
+            })
+    }
+
+    fn call(&mut self, name: &str, args: &'a [Expr], span: Span) -> anyhow::Result<Value> {
+        let captures = match self.functions.get(name) {
+            Some(f) => f.captures,
+            None => return Err(Diagnostic::new("function must be defined", span).into()),
+        };
+
+        // the caller either owns the captured variables or captured them itself
+        let mut env = HashMap::new();
+        for captured in captures {
+            env.insert(captured.as_str(), self.lookup(captured, span)?);
+        }
+
+        self.invoke(name, env, args, span)
+    }
+
+    fn call_value(
+        &mut self,
+        callee: &'a Expr,
+        args: &'a [Expr],
+        span: Span,
+    ) -> anyhow::Result<Value> {
+        let Value::Function(f) = self.eval(callee)? else {
+            unreachable!("the checker only allows calls through functions")
+        };
+        let closure = &self.closures[f];
+        let (name, env) = (closure.function, closure.env.clone());
+        self.invoke(name, env, args, span)
+    }
+
+    // the value of a named function or a lambda, capturing copies of what it
+    // needs from the current scope
+    fn closure(&mut self, name: &'a str, span: Span) -> anyhow::Result<Value> {
+        if let Some(&shared) = self.shared.get(name) {
+            return Ok(Value::Function(shared));
+        }
+        if self.functions[name].captures.is_empty() {
+            self.shared.insert(name, self.closures.len());
+        }
+
+        let mut env = HashMap::new();
+        for captured in self.functions[name].captures {
+            let value = self.lookup(captured, span)?.borrow().clone();
+            env.insert(captured.as_str(), Rc::new(RefCell::new(value)));
+        }
+
+        self.closures.push(Closure {
+            function: name,
+            env,
+        });
+
+        Ok(Value::Function(self.closures.len() - 1))
+    }
+
+    fn invoke(
+        &mut self,
+        name: &str,
+        mut frame: HashMap<&'a str, Rc<RefCell<Value>>>,
+        args: &'a [Expr],
+        span: Span,
+    ) -> anyhow::Result<Value> {
+        let (params, body) = {
+            let f = &self.functions[name];
+            (f.args, f.body)
+        };
+
+        if stack_address() < self.limit {
+            return Err(Diagnostic::new("recursion too deep", span)
+                .with_label(format!(
+                    "{} is called too deeply here",
+                    describe_function(name)
+                ))
+                .into());
+        }
+
+        if params.len() != args.len() {
+            return Err(Diagnostic::new(
+                format!(
+                    "{} takes {} arguments but {} were given",
+                    describe_function(name),
+                    params.len(),
+                    args.len()
+                ),
+                span,
+            )
+            .into());
+        }
+
+        for (param, arg) in params.iter().zip(args) {
+            let ty = self.types.variable(&param.name);
+            let value = self.eval(arg)?.of_type(&ty);
+            frame.insert(param.name.as_str(), Rc::new(RefCell::new(value)));
+        }
+
+        let outer = std::mem::replace(&mut self.scopes, vec![frame]);
+        let result = self.exec_body(body, true);
+        self.scopes = outer;
+
+        match result? {
+            Flow::Return(value) => Ok(value.of_type(&self.types.returns(name))),
+            _ => Err(Diagnostic::new(
+                format!("{} did not return a value", describe_function(name)),
+                span,
+            )
+            .into()),
+        }
+    }
+
+    fn eval(&mut self, expr: &'a Expr) -> anyhow::Result<Value> {
+        use Ordering::*;
+        use Value::*;
+
+        Ok(match &expr.kind {
+            ExprKind::Int(i) => Int(*i),
+            ExprKind::Float(d) => Double(*d),
+            ExprKind::Bool(b) => Bool(*b),
+            ExprKind::Str(_) => unreachable!("the resolver only allows strings in `print`"),
+            ExprKind::Ident(ident) => self.lookup(ident, expr.span)?.borrow().clone(),
+            ExprKind::Call { name, args } => self.call(name, args, expr.span)?,
+            ExprKind::CallValue { callee, args } => self.call_value(callee, args, expr.span)?,
+            ExprKind::Function(name) | ExprKind::Lambda { name, .. } => {
+                self.closure(name, expr.span)?
+            }
+            ExprKind::Add(lhs, rhs) => match (self.eval(lhs)?, self.eval(rhs)?) {
+                (Int(a), Int(b)) => Int(a.wrapping_add(b)),
+                (a, b) => Double(a.as_double() + b.as_double()),
+            },
+            ExprKind::Sub(lhs, rhs) => match (self.eval(lhs)?, self.eval(rhs)?) {
+                (Int(a), Int(b)) => Int(a.wrapping_sub(b)),
+                (a, b) => Double(a.as_double() - b.as_double()),
+            },
+            ExprKind::Mul(lhs, rhs) => match (self.eval(lhs)?, self.eval(rhs)?) {
+                (Int(a), Int(b)) => Int(a.wrapping_mul(b)),
+                (a, b) => Double(a.as_double() * b.as_double()),
+            },
+            ExprKind::Div(lhs, rhs) => match (self.eval(lhs)?, self.eval(rhs)?) {
+                (Int(_), Int(0)) => {
+                    return Err(Diagnostic::new("integer division by zero", expr.span).into())
+                }
+                (Int(a), Int(b)) => Int(a.wrapping_div(b)),
+                (a, b) => Double(a.as_double() / b.as_double()),
+            },
+            ExprKind::Pow(lhs, rhs) => {
+                let lhs = self.eval(lhs)?.as_double();
+                Double(lhs.powf(self.eval(rhs)?.as_double()))
+            }
+            ExprKind::Mod(lhs, rhs) => match (self.eval(lhs)?, self.eval(rhs)?) {
+                (Int(_), Int(0)) => {
+                    return Err(Diagnostic::new("integer division by zero", expr.span).into())
+                }
+                (Int(a), Int(b)) => Int(a.wrapping_rem(b)),
+                (a, b) => Double(a.as_double() % b.as_double()),
+            },
+            ExprKind::Leq(lhs, rhs) => Bool(matches!(self.compare(lhs, rhs)?, Some(Less | Equal))),
+            ExprKind::Geq(lhs, rhs) => {
+                Bool(matches!(self.compare(lhs, rhs)?, Some(Greater | Equal)))
+            }
+            ExprKind::Lt(lhs, rhs) => Bool(self.compare(lhs, rhs)? == Some(Less)),
+            ExprKind::Gt(lhs, rhs) => Bool(self.compare(lhs, rhs)? == Some(Greater)),
+            ExprKind::Eq(lhs, rhs) => Bool(self.compare(lhs, rhs)? == Some(Equal)),
+            ExprKind::Neq(lhs, rhs) => Bool(self.compare(lhs, rhs)? != Some(Equal)),
+            // `and` and `or` only evaluate their right side if they need to
+            ExprKind::And(lhs, rhs) => Bool(self.eval(lhs)?.truthy() && self.eval(rhs)?.truthy()),
+            ExprKind::Or(lhs, rhs) => Bool(self.eval(lhs)?.truthy() || self.eval(rhs)?.truthy()),
+            ExprKind::Xor(lhs, rhs) => {
+                let lhs = self.eval(lhs)?.truthy();
+                Bool(lhs != self.eval(rhs)?.truthy())
+            }
+            ExprKind::Neg(e) => match self.eval(e)? {
+                Int(i) => Int(i.wrapping_neg()),
+                Double(d) => Double(-d),
+                Bool(_) | Array(_) | Struct(_) | Enum(..) | Function(_) => {
+                    unreachable!("the checker only negates numbers")
+                }
+            },
+            ExprKind::Pos(e) => self.eval(e)?,
+            ExprKind::Not(e) => Bool(!self.eval(e)?.truthy()),
+            ExprKind::Array(elements) => {
+                let element = element_type(expr, self.types);
+                let mut values = Vec::with_capacity(elements.len());
+                for e in elements {
+                    let value = self.eval(e)?.of_type(&element);
+                    values.push(value);
+                }
+                self.arrays.push(values);
+                Array(self.arrays.len() - 1)
+            }
+            ExprKind::NewArray { len, fill } => {
+                let len = self.eval(len)?.as_int();
+                let element = element_type(expr, self.types);
+                let value = match fill {
+                    Some(fill) => self.eval(fill)?.of_type(&element),
+                    None => Int(0).of_type(&element),
+                };
+                if len < 0 {
+                    return Err(Diagnostic::new(
+                        format!("an array can't have a length of {}", len),
+                        expr.span,
+                    )
+                    .into());
+                }
+                let values = vec![value; len as usize];
+                self.arrays.push(values);
+                Array(self.arrays.len() - 1)
+            }
+            ExprKind::Index { array, index } => {
+                let (a, i) = self.index(array, index)?;
+                self.arrays[a][i].clone()
+            }
+            ExprKind::Len(array) => match self.eval(array)? {
+                Array(a) => Int(self.arrays[a].len() as i64),
+                _ => unreachable!("the checker only allows `len` of arrays"),
+            },
+            // in the order the fields are declared, evaluated in the order
+            // they are written
+            ExprKind::Struct { name, fields } => {
+                let declared = &self.types.structs[name];
+                let mut values = vec![Int(0); declared.len()];
+                for (field, value) in fields {
+                    let i = declared.iter().position(|(f, _)| f == field).unwrap();
+                    let value = self.eval(value)?.of_type(&declared[i].1);
+                    values[i] = value;
+                }
+                Struct(values.into())
+            }
+            ExprKind::Field { expr: e, name } => {
+                let i = self.field(e, name);
+                match self.eval(e)? {
+                    Struct(fields) => fields[i].clone(),
+                    _ => unreachable!("the checker only allows fields of structs"),
+                }
+            }
+            ExprKind::Variant { ty, name, fields } => {
+                let variant = self.types.enums[ty].iter().position(|v| v == name).unwrap();
+                let declared = &self.types.structs[name];
+                let mut values = vec![Int(0); declared.len()];
+                for (field, value) in fields {
+                    let i = declared.iter().position(|(f, _)| f == field).unwrap();
+                    let value = self.eval(value)?.of_type(&declared[i].1);
+                    values[i] = value;
+                }
+                Enum(variant, values.into())
+            }
+            ExprKind::Match { value, arms } => {
+                let arm = self.choose(value, arms)?;
+                let result = self.eval(&arm.body);
+                self.scopes.pop();
+                result?.of_type(&type_of(expr, self.types))
+            }
+            ExprKind::Record(_) => unreachable!("the resolver makes records into structs"),
+        })
+    }
+
+    // the first arm whose pattern matches `value` and whose guard holds, with
+    // a scope for what its pattern binds pushed, for the caller to pop once
+    // it has run the arm
+    fn choose<T>(&mut self, value: &'a Expr, arms: &'a [Arm<T>]) -> anyhow::Result<&'a Arm<T>> {
+        let ty = type_of(value, self.types);
+        let value = self.eval(value)?;
+        for arm in arms {
+            let mut binds = Vec::new();
+            if !self.matches(&arm.pattern, &value, &ty, &mut binds) {
+                continue;
+            }
+            let mut scope = HashMap::new();
+            for (name, value) in binds {
+                let value = value.of_type(&self.types.variable(name));
+                scope.insert(name, Rc::new(RefCell::new(value)));
+            }
+            self.scopes.push(scope);
+            let chosen = match &arm.guard {
+                Some(guard) => self.eval(guard).map(|value| value.truthy()),
+                None => Ok(true),
+            };
+            match chosen {
+                Ok(true) => return Ok(arm),
+                Ok(false) => {
+                    self.scopes.pop();
+                }
+                Err(e) => {
+                    self.scopes.pop();
+                    return Err(e);
+                }
+            }
+        }
+        unreachable!("the checker makes every `match` cover every case")
+    }
+
+    // whether `pattern` matches `value`, of the type `ty`, adding what it
+    // binds to `binds`
+    fn matches(
+        &self,
+        pattern: &'a Pattern,
+        value: &Value,
+        ty: &Type,
+        binds: &mut Vec<(&'a str, Value)>,
+    ) -> bool {
+        match (&pattern.kind, value, ty) {
+            (PatternKind::Wildcard, ..) => true,
+            (PatternKind::Binding(name), ..) => {
+                binds.push((name, value.clone()));
+                true
+            }
+            (PatternKind::Int(n), value, _) => value.as_int() == *n,
+            (PatternKind::Bool(b), value, _) => value.truthy() == *b,
+            (
+                PatternKind::Constructor { name, fields },
+                Value::Enum(variant, s),
+                Type::Enum(ty),
+            ) => {
+                self.types.enums[ty][*variant] == *name
+                    && self.matches_fields(name, fields, s, binds)
+            }
+            (PatternKind::Constructor { name, fields }, Value::Struct(s), Type::Struct(_)) => {
+                self.matches_fields(name, fields, s, binds)
+            }
+            _ => unreachable!("the checker only takes apart variants and structs"),
+        }
+    }
+
+    fn matches_fields(
+        &self,
+        name: &str,
+        fields: &'a [Pattern],
+        values: &[Value],
+        binds: &mut Vec<(&'a str, Value)>,
+    ) -> bool {
+        let declared = &self.types.structs[name];
+        fields
+            .iter()
+            .zip(declared)
+            .zip(values)
+            .all(|((pattern, (_, ty)), value)| self.matches(pattern, value, ty, binds))
+    }
+
+    // where the field `name` is in the struct `expr`
+    fn field(&self, expr: &Expr, name: &str) -> usize {
+        match type_of(expr, self.types) {
+            Type::Struct(ty) => {
+                let fields = &self.types.structs[&ty];
+                fields.iter().position(|(f, _)| f == name).unwrap()
+            }
+            _ => unreachable!("the checker only allows fields of structs"),
+        }
+    }
+
+    // the variable or element the place `expr` is stored in, adding the
+    // fields taken to get to it from there to `path`
+    fn place(&mut self, expr: &'a Expr, path: &mut Vec<usize>) -> anyhow::Result<Place> {
+        match &expr.kind {
+            ExprKind::Ident(name) => Ok(Place::Variable(self.lookup(name, expr.span)?)),
+            ExprKind::Index { array, index } => {
+                let (a, i) = self.index(array, index)?;
+                Ok(Place::Element(a, i))
+            }
+            ExprKind::Field { expr: e, name } => {
+                let place = self.place(e, path)?;
+                path.push(self.field(e, name));
+                Ok(place)
+            }
+            _ => unreachable!("the resolver only allows fields of places to be assigned to"),
+        }
+    }
+
+    // the array and element `array[index]` refers to, which is an error if
+    // the index is out of bounds, as it is in the compiled program
+    fn index(&mut self, array: &'a Expr, index: &'a Expr) -> anyhow::Result<(usize, usize)> {
+        let a = match self.eval(array)? {
+            Value::Array(a) => a,
+            _ => unreachable!("the checker only allows arrays to be indexed"),
+        };
+        let i = self.eval(index)?.as_int();
+        let len = self.arrays[a].len();
+        match usize::try_from(i) {
+            Ok(i) if i < len => Ok((a, i)),
+            _ => Err(Diagnostic::new(
+                format!(
+                    "index {} is out of bounds for `{}`, which has a length of {}",
+                    i,
+                    &self.source[array.span.start..array.span.end],
+                    len
+                ),
+                index.span,
+            )
+            .into()),
+        }
+    }
+
+    // `expr` as `print` writes it
+    fn print_value(&mut self, expr: &'a Expr, format: Format) -> anyhow::Result<String> {
+        let value = self.eval(expr)?;
+        Ok(match type_of(expr, self.types) {
+            ty @ (Type::Array(_) | Type::Struct(_) | Type::Enum(_)) => self.show(&value, &ty),
+            _ => format_with(value, format),
+        })
+    }
+
+    // arrays print as `[1, 2, 3]`, structs as `Point(x: 1, y: 2)` and enums
+    // as their variant does, `Circle(r: 1.5)`, or just `Empty` if it holds
+    // nothing, with what they hold as it prints alone
+    fn show(&self, value: &Value, ty: &Type) -> String {
+        match (value, ty) {
+            (Value::Struct(s), Type::Struct(name)) => self.show_fields(name, s),
+            (Value::Enum(variant, s), Type::Enum(name)) => {
+                let variant = &self.types.enums[name][*variant];
+                if self.types.structs[variant].is_empty() {
+                    variant.clone()
+                } else {
+                    self.show_fields(variant, s)
+                }
+            }
+            (Value::Array(a), Type::Array(element)) => {
+                let elements = self.arrays[*a]
+                    .iter()
+                    .map(|e| self.show(e, element))
+                    .collect::<Vec<_>>();
+                format!("[{}]", elements.join(", "))
+            }
+            (Value::Function(_), ty) => ty.to_string(),
+            (value, ty) => format_value(value.clone().of_type(ty)),
+        }
+    }
+
+    fn show_fields(&self, name: &str, values: &[Value]) -> String {
+        let fields = self.types.structs[name]
+            .iter()
+            .zip(values)
+            .map(|((field, ty), value)| format!("{}: {}", field, self.show(value, ty)))
+            .collect::<Vec<_>>();
+        format!("{}({})", name, fields.join(", "))
+    }
+
+    fn compare(&mut self, lhs: &'a Expr, rhs: &'a Expr) -> anyhow::Result<Option<Ordering>> {
+        let lhs = self.eval(lhs)?;
+        Ok(compare(&lhs, &self.eval(rhs)?))
+    }
+}
+
+// where a field assignment stores its value, see `Interpreter::place`
+enum Place {
+    Variable(Rc<RefCell<Value>>),
+    Element(usize, usize),
+}
+
+// stores `value` at the end of `path` in `target`, giving the structs on the
+// way fields of their own if they share them with copies
+fn assign(target: &mut Value, path: &[usize], value: Value) {
+    match (path.split_first(), target) {
+        (None, target) => *target = value,
+        (Some((&i, path)), Value::Struct(fields)) => {
+            assign(&mut Rc::make_mut(fields)[i], path, value)
+        }
+        _ => unreachable!("the checker only allows fields of structs"),
+    }
+}
+
+// the type of the elements of the array `expr`
+fn element_type(expr: &Expr, types: &Types) -> Type {
+    match type_of(expr, types) {
+        Type::Array(element) => *element,
+        _ => unreachable!("the checker only allows arrays here"),
+    }
+}
+
+// how many times a range of floats runs, as `cx_range_count` counts it
+fn range_count(start: f64, stop: f64, step: f64, inclusive: bool) -> f64 {
+    let mut n = (stop - start) / step;
+    let whole = n.round();
+    if (n - whole).abs() <= 1e-9 * n.abs().max(1.0) {
+        n = whole;
+    }
+    n = if inclusive { n.floor() + 1.0 } else { n.ceil() };
+    if n > 0.0 {
+        n
+    } else {
+        0.0
+    }
+}
+
+// C comparisons are false whenever either side is NaN, except `!=`
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (a, b) => a.as_double().partial_cmp(&b.as_double()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checker::Checker;
+    use crate::parser::parse;
+    use crate::resolver::Resolver;
+
+    // what `source` prints, followed by the error that stopped it, if any
+    fn run(source: &str) -> String {
+        let (mut program, _) = parse(source).expect("parses");
+        Resolver::resolve(&mut program, source).expect("resolves");
+        let types = Checker::check(&program, source).expect("checks");
+        let mut out = Vec::new();
+        let result = Interpreter::run_with(&program, &types, source, &mut out);
+        let mut printed = String::from_utf8(out).unwrap();
+        if let Err(e) = result {
+            printed.push_str(&format!("error: {}", e));
+        }
+        printed
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(
+            run("print(9223372036854775807 + 1, -7 / 2, -7 % 2, 7.0 / 2, 2 ^ 10)"),
+            "-9223372036854775808 -3 -1 3.500000 1024\n"
+        );
+        assert_eq!(
+            run("print(1 < 2 and 2.5 >= 2, 0.1 + 0.2 == 0.3)"),
+            "true false\n"
+        );
+        assert_eq!(
+            run("print(\"{3.14159:8.2}|{42:4}|{1500.0:.1e}\")"),
+            "    3.14|  42|1.5e+03\n"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let counter = "counter() do\nlocal n = 0\nstep() do\nn = n + 1\nn\nend\nstep\nend\n";
+        assert_eq!(
+            run(&format!(
+                "{}local c = counter()\nprint(c(), c(), c())",
+                counter
+            )),
+            "1 2 3\n"
+        );
+        assert_eq!(
+            run("fib(n) do\nif n < 2 do\nreturn n\nend\nfib(n - 1) + fib(n - 2)\nend\nfib(20)"),
+            "6765\n"
+        );
+        assert_eq!(
+            run("apply(f: fn(int) -> int, x) = f(x)\nprint(apply(fn(n: int) -> int => n * 3, 7))"),
+            "21\n"
+        );
+    }
+
+    #[test]
+    fn values() {
+        // arrays are shared, structs are copied
+        assert_eq!(
+            run("struct P do x, y end\nlocal a = [1, 2]\nlocal b = a\nb[0] = 5\nlocal p = P(1, 2)\nlocal q = p\nq.x = 9\nprint(a, p, q)"),
+            "[5, 2] P(x: 1, y: 2) P(x: 9, y: 2)\n"
+        );
+        // including the structs they hold, wherever the copy is stored
+        assert_eq!(
+            run("struct V do x, y end\nstruct B do pos: V, n end\nlocal a = [B(V(1, 2), 0)]\nlocal b = a[0]\na[0].pos.x = 5\nb.pos.y = 7\nlocal c = b\nc.n = 3\nprint(a, b, c)"),
+            "[B(pos: V(x: 5, y: 2), n: 0)] B(pos: V(x: 1, y: 7), n: 0) B(pos: V(x: 1, y: 7), n: 3)\n"
+        );
+        assert_eq!(
+            run("enum S do C(r), N end\narea(s) = match s do C(r) => r * r, N => 0 end\nprint(area(C(3)), area(N))"),
+            "9 0\n"
+        );
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(
+            run("local a = [1, 2]\nprint(\"a\")\nprint(a[2])"),
+            "a\nerror: index 2 is out of bounds for `a`, which has a length of 2"
+        );
+        // the text before a value is only printed once it has been evaluated
+        assert_eq!(
+            run("local z = 0\nprint(\"before\", 1 / z)"),
+            "error: integer division by zero"
+        );
+    }
+
+    #[test]
+    fn float_ranges() {
+        let values = run("for t in 0..1 step 0.1 do\nprint(\"{t:.17}\")\nend");
+        assert_eq!(values.lines().count(), 10);
+        assert_eq!(values.lines().last(), Some("0.90000000000000002"));
+
+        assert_eq!(
+            run("for t in 0..=0.3 step 0.1 do\nprint(\"{t:.2}\")\nend"),
+            "0.00\n0.10\n0.20\n0.30\n"
+        );
+        assert_eq!(
+            run("for t in 1..=0 step -0.25 do\nprint(t)\nend"),
+            "1\n0.750000\n0.500000\n0.250000\n0\n"
+        );
+    }
+}
@@ -1,14 +1,21 @@
 extern crate nom;
 
+mod checker;
 mod compiler;
+mod diagnostics;
+mod interpreter;
+mod matching;
 mod parser;
-mod shunting_yard;
+mod resolver;
 mod types;
 mod utils;
 
+use checker::Checker;
 use compiler::Compiler;
+use diagnostics::Diagnostic;
+use interpreter::Interpreter;
 use parser::parse;
-use types::*;
+use resolver::Resolver;
 
 use clap::Parser;
 use std::fs::read_to_string;
@@ -27,6 +34,26 @@ pub struct Args {
         help = "The file that the compiled program will output to"
     )]
     output: Option<String>,
+
+    #[clap(
+        short,
+        long,
+        help = "Interpret the program directly instead of compiling it with gcc"
+    )]
+    run: bool,
+
+    #[clap(
+        long,
+        help = "Leave out the checks that array indexes are in bounds from the compiled program"
+    )]
+    unchecked: bool,
+}
+
+fn report(diagnostics: &[Diagnostic], filename: &str, code: &str) -> ! {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(filename, code));
+    }
+    std::process::exit(1)
 }
 
 fn main() -> anyhow::Result<()> {
@@ -34,9 +61,35 @@ fn main() -> anyhow::Result<()> {
 
     let code = read_to_string(&args.filename)?;
 
-    let program = parse(&code);
+    // comments only matter to tools working on the source itself
+    let mut program = match parse(&code) {
+        Ok((program, _)) => program,
+        Err(diagnostics) => report(&diagnostics, &args.filename, &code),
+    };
+
+    if let Err(diagnostics) = Resolver::resolve(&mut program, &code) {
+        report(&diagnostics, &args.filename, &code);
+    }
+
+    let types = match Checker::check(&program, &code) {
+        Ok(types) => types,
+        Err(diagnostics) => report(&diagnostics, &args.filename, &code),
+    };
+
+    let filename = args.filename.clone();
+
+    let result = if args.run {
+        Interpreter::run(&program, &types, &code)
+    } else {
+        Compiler::compile(&program, &types, &code, args)
+    };
 
-    Compiler::compile(&program, args)?;
+    if let Err(e) = result {
+        match e.downcast_ref::<Diagnostic>() {
+            Some(diagnostic) => report(std::slice::from_ref(diagnostic), &filename, &code),
+            None => return Err(e),
+        }
+    }
 
     Ok(())
 }
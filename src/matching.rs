@@ -0,0 +1,316 @@
+use crate::checker::Types;
+use crate::types::*;
+
+// Exhaustiveness and reachability of `match` arms.
+//
+// Patterns are checked as rows of a matrix, one column for each value still
+// to be taken apart, following Maranget's "Warnings for pattern matching".
+// A pattern vector is useful against the rows above it if some value matches
+// it but none of them. An arm can never match if its pattern is not useful
+// against the arms before it, and a `match` is exhaustive if `_` is not
+// useful against all of its arms. Arms with a guard might not match what
+// their pattern does, so they only count for reachability of themselves.
+//
+// Bools have two constructors, `true` and `false`, a struct has one, itself,
+// and an enum has its variants. Ints have too many to ever list, so they are
+// only covered by something that matches anything, and floats, arrays and
+// functions can't be taken apart at all.
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Named(String),
+    Int(i64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+impl Pat {
+    fn from(pattern: &Pattern) -> Pat {
+        match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Binding(_) => Pat::Wild,
+            PatternKind::Int(n) => Pat::Ctor(Ctor::Int(*n), vec![]),
+            PatternKind::Bool(b) => Pat::Ctor(Ctor::Bool(*b), vec![]),
+            PatternKind::Constructor { name, fields } => Pat::Ctor(
+                Ctor::Named(name.clone()),
+                fields.iter().map(Pat::from).collect(),
+            ),
+        }
+    }
+
+    // as it would be written in the source
+    fn show(&self) -> String {
+        match self {
+            Pat::Wild => String::from("_"),
+            Pat::Ctor(Ctor::Int(n), _) => n.to_string(),
+            Pat::Ctor(Ctor::Bool(b), _) => b.to_string(),
+            Pat::Ctor(Ctor::Named(name), args) if args.is_empty() => name.clone(),
+            Pat::Ctor(Ctor::Named(name), args) => {
+                let args = args.iter().map(Pat::show).collect::<Vec<_>>();
+                format!("{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+// the types of the fields of `ctor`
+fn fields(ctor: &Ctor, types: &Types) -> Vec<Type> {
+    match ctor {
+        Ctor::Named(name) => types.structs[name]
+            .iter()
+            .map(|(_, ty)| ty.clone())
+            .collect(),
+        Ctor::Int(_) | Ctor::Bool(_) => vec![],
+    }
+}
+
+// every constructor of `ty`, if there are few enough to list
+fn signature(ty: &Type, types: &Types) -> Option<Vec<Ctor>> {
+    match ty {
+        Type::Bool => Some(vec![Ctor::Bool(true), Ctor::Bool(false)]),
+        Type::Struct(name) => Some(vec![Ctor::Named(name.clone())]),
+        Type::Enum(name) => Some(
+            types.enums[name]
+                .iter()
+                .map(|variant| Ctor::Named(variant.clone()))
+                .collect(),
+        ),
+        Type::Int | Type::Float | Type::Array(_) | Type::Function(..) => None,
+    }
+}
+
+// the constructors at the start of the rows
+fn heads(rows: &[Vec<Pat>]) -> Vec<&Ctor> {
+    let mut heads = Vec::new();
+    for row in rows {
+        if let Pat::Ctor(ctor, _) = &row[0] {
+            if !heads.contains(&ctor) {
+                heads.push(ctor);
+            }
+        }
+    }
+    heads
+}
+
+// the rows that match values made by `ctor`, with its fields in place of the
+// first column
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut specialized = match &row[0] {
+                Pat::Ctor(c, args) if c == ctor => args.clone(),
+                Pat::Ctor(..) => return None,
+                Pat::Wild => vec![Pat::Wild; arity],
+            };
+            specialized.extend_from_slice(&row[1..]);
+            Some(specialized)
+        })
+        .collect()
+}
+
+// the rows that match anything in the first column, without it
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+// whether some value of the types `tys` matches `row` but none of `rows`
+fn useful(rows: &[Vec<Pat>], row: &[Pat], tys: &[Type], types: &Types) -> bool {
+    let Some(ty) = tys.first() else {
+        return rows.is_empty();
+    };
+    let specialized = |ctor: &Ctor, args: &[Pat]| {
+        let mut field_tys = fields(ctor, types);
+        field_tys.extend_from_slice(&tys[1..]);
+        let mut rest = args.to_vec();
+        rest.extend_from_slice(&row[1..]);
+        useful(
+            &specialize(rows, ctor, args.len()),
+            &rest,
+            &field_tys,
+            types,
+        )
+    };
+    match &row[0] {
+        Pat::Ctor(ctor, args) => specialized(ctor, args),
+        Pat::Wild => match signature(ty, types) {
+            Some(all) if all.iter().all(|c| heads(rows).contains(&c)) => all
+                .iter()
+                .any(|ctor| specialized(ctor, &vec![Pat::Wild; fields(ctor, types).len()])),
+            _ => useful(&default(rows), &row[1..], &tys[1..], types),
+        },
+    }
+}
+
+// values of the types `tys` that none of `rows` match, if there are any, as
+// the patterns that would match them
+fn missing(rows: &[Vec<Pat>], tys: &[Type], types: &Types) -> Option<Vec<Pat>> {
+    let Some(ty) = tys.first() else {
+        return rows.is_empty().then(Vec::new);
+    };
+    let used = heads(rows);
+    match signature(ty, types) {
+        Some(all) if all.iter().all(|c| used.contains(&c)) => all.iter().find_map(|ctor| {
+            let mut field_tys = fields(ctor, types);
+            let arity = field_tys.len();
+            field_tys.extend_from_slice(&tys[1..]);
+            let mut found = missing(&specialize(rows, ctor, arity), &field_tys, types)?;
+            let rest = found.split_off(arity);
+            Some(
+                std::iter::once(Pat::Ctor(ctor.clone(), found))
+                    .chain(rest)
+                    .collect(),
+            )
+        }),
+        all => {
+            let rest = missing(&default(rows), &tys[1..], types)?;
+            // a constructor none of the rows start with, or `_` if they all
+            // match anything here
+            let head = match all {
+                _ if used.is_empty() => Pat::Wild,
+                Some(all) => {
+                    let ctor = all.into_iter().find(|c| !used.contains(&c)).unwrap();
+                    let arity = fields(&ctor, types).len();
+                    Pat::Ctor(ctor, vec![Pat::Wild; arity])
+                }
+                None if *ty == Type::Int => {
+                    let n = (0..).find(|n| !used.contains(&&Ctor::Int(*n))).unwrap();
+                    Pat::Ctor(Ctor::Int(n), vec![])
+                }
+                None => Pat::Wild,
+            };
+            Some(std::iter::once(head).chain(rest).collect())
+        }
+    }
+}
+
+// the arms that can never match, by index, as the arms without a guard
+// before them match everything they do
+pub fn redundant(ty: &Type, arms: &[(&Pattern, bool)], types: &Types) -> Vec<usize> {
+    let mut rows = Vec::new();
+    let mut redundant = Vec::new();
+    for (i, &(pattern, guarded)) in arms.iter().enumerate() {
+        let row = vec![Pat::from(pattern)];
+        if !useful(&rows, &row, std::slice::from_ref(ty), types) {
+            redundant.push(i);
+        }
+        if !guarded {
+            rows.push(row);
+        }
+    }
+    redundant
+}
+
+// a value the arms without a guard don't match, written as a pattern, if
+// there is one
+pub fn uncovered(ty: &Type, arms: &[(&Pattern, bool)], types: &Types) -> Option<String> {
+    let rows = arms
+        .iter()
+        .filter(|(_, guarded)| !guarded)
+        .map(|(pattern, _)| vec![Pat::from(pattern)])
+        .collect::<Vec<_>>();
+    let found = missing(&rows, std::slice::from_ref(ty), types)?;
+    Some(found[0].show())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checker::Checker;
+    use crate::parser::parse;
+    use crate::resolver::Resolver;
+
+    // the label of the first error the checker finds in `source`, if any
+    fn label(source: &str) -> Option<String> {
+        let (mut program, _) = parse(source).expect("parses");
+        Resolver::resolve(&mut program, source).expect("resolves");
+        let errors = Checker::check(&program, source).err()?;
+        Some(errors[0].label.clone().unwrap_or_default())
+    }
+
+    const SHAPE: &str = "enum Shape do Circle(r), Square(s), Empty end\n";
+
+    #[test]
+    fn exhaustive() {
+        let area = |arms: &str| {
+            label(&format!(
+                "{}area(s: Shape) = match s do {} end",
+                SHAPE, arms
+            ))
+        };
+        assert_eq!(area("Circle(r) => r, Square(s) => s * s, Empty => 0"), None);
+        assert_eq!(area("Circle(r) => r, _ => 0"), None);
+        assert_eq!(
+            area("Circle(r) => r, Empty => 0").as_deref(),
+            Some("nothing matches `Square(_)`")
+        );
+        // a guard might not match, so its arm doesn't cover anything
+        assert_eq!(
+            area("Circle(r) if r > 1 => r, Square(s) => s, Empty => 0").as_deref(),
+            Some("nothing matches `Circle(_)`")
+        );
+        assert_eq!(
+            label("f(b: bool) = match b do true => 1 end").as_deref(),
+            Some("nothing matches `false`")
+        );
+        assert_eq!(
+            label("f(b: bool) = match b do true => 1, false => 0 end"),
+            None
+        );
+        // ints are only covered by something that matches anything
+        assert_eq!(
+            label("f(n: int) = match n do 0 => 1, 1 => 2 end").as_deref(),
+            Some("nothing matches `2`")
+        );
+    }
+
+    #[test]
+    fn nested() {
+        let source = "enum Opt do Some(v: bool), None end\n";
+        assert_eq!(
+            label(&format!(
+                "{}f(o: Opt) = match o do Some(true) => 1, None => 0 end",
+                source
+            ))
+            .as_deref(),
+            Some("nothing matches `Some(false)`")
+        );
+        assert_eq!(
+            label(&format!(
+                "{}f(o: Opt) = match o do Some(true) => 1, Some(_) => 2, None => 0 end",
+                source
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn reachable() {
+        let unreachable = Some(String::from("the arms before it match everything it does"));
+        assert_eq!(
+            label(&format!(
+                "{}area(s: Shape) = match s do Circle(r) => r, _ => 0, Empty => 1 end",
+                SHAPE
+            )),
+            unreachable
+        );
+        assert_eq!(
+            label("f(n: int) = match n do _ => 1, 0 => 2 end"),
+            unreachable
+        );
+        assert_eq!(
+            label("f(b: bool) = match b do true => 1, false => 0, true => 2 end"),
+            unreachable
+        );
+        // an arm after a guarded one like it can still match
+        assert_eq!(
+            label("f(n: int) = match n do 0 if n > 1 => 1, 0 => 2, _ => 3 end"),
+            None
+        );
+    }
+}
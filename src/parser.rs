@@ -1,280 +1,1255 @@
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{alpha1, alphanumeric1, digit0, digit1, multispace0},
-    combinator::{map, map_res, opt, recognize, success},
-    error::ParseError,
+    character::complete::{alpha1, alphanumeric1, digit0, digit1, not_line_ending},
+    combinator::{cut, map, map_res, not, opt, recognize, success, value},
+    error::{ErrorKind, FromExternalError, ParseError},
     multi::{many0, many0_count, many_m_n},
-    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
-    IResult,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult, Parser,
 };
+use std::cell::{Cell, RefCell};
 
-use crate::shunting_yard::shunting_yard;
+use crate::diagnostics::{line_col, nearest_keyword, Diagnostic};
 use crate::types::*;
 
-fn ws<'a, F: 'a, O, E: ParseError<&'a str>>(
-    inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+thread_local! {
+    // address of the source being parsed, so spans can be computed from the
+    // remaining input slices nom hands around
+    static SOURCE: Cell<usize> = const { Cell::new(0) };
+    // every comment skipped so far, in source order; backtracking skips the
+    // same comment more than once, so each is only recorded the first time
+    static COMMENTS: RefCell<Vec<Span>> = const { RefCell::new(Vec::new()) };
+}
+
+fn offset(input: &str) -> usize {
+    input.as_ptr() as usize - SOURCE.with(Cell::get)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expected {
+    Token(&'static str),
+    Description(&'static str),
+}
+
+#[derive(Debug)]
+pub struct Error<'a> {
+    input: &'a str,
+    expected: Vec<Expected>,
+    // a message and the length of the text it is about, replacing the usual
+    // `unexpected ...` for mistakes inside a single token
+    message: Option<(&'static str, usize)>,
+}
+
+impl<'a> ParseError<&'a str> for Error<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        Self {
+            input,
+            expected: vec![],
+            message: None,
+        }
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    // keep whichever alternative got furthest, merging what they expected
+    // when they failed at the same place
+    fn or(mut self, other: Self) -> Self {
+        use std::cmp::Ordering::*;
+        match self.input.len().cmp(&other.input.len()) {
+            Less => self,
+            Greater => other,
+            Equal => {
+                for e in other.expected {
+                    if !self.expected.contains(&e) {
+                        self.expected.push(e);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<'a, E> FromExternalError<&'a str, E> for Error<'a> {
+    fn from_external_error(input: &'a str, kind: ErrorKind, _: E) -> Self {
+        Self::from_error_kind(input, kind)
+    }
+}
+
+impl<'a> Error<'a> {
+    fn malformed(input: &'a str, message: &'static str, len: usize, expected: Expected) -> Self {
+        Self {
+            input,
+            expected: vec![expected],
+            message: Some((message, len)),
+        }
+    }
+
+    fn into_diagnostic(self, source: &str) -> Diagnostic {
+        let start = offset(self.input);
+
+        if let Some((message, len)) = self.message {
+            let mut diagnostic = Diagnostic::new(message, Span::new(start, start + len));
+            if let Some(Expected::Description(label)) = self.expected.first() {
+                diagnostic = diagnostic.with_label(format!("expected {}", label));
+            }
+            return diagnostic;
+        }
+        let found = self
+            .input
+            .split(char::is_whitespace)
+            .next()
+            .filter(|s| !s.is_empty());
+
+        let mut diagnostic = match found {
+            Some(found) => {
+                let len = found
+                    .char_indices()
+                    .find(|&(i, c)| i > 0 && !(c.is_alphanumeric() || c == '_'))
+                    .map(|(i, _)| i)
+                    .unwrap_or(found.len());
+                Diagnostic::new(
+                    format!("unexpected `{}`", &found[..len]),
+                    Span::new(start, start + len),
+                )
+            }
+            None => Diagnostic::new("unexpected end of input", Span::new(start, start)),
+        };
+
+        if !self.expected.is_empty() {
+            let expected = self
+                .expected
+                .iter()
+                .map(|e| match e {
+                    Expected::Token(t) => format!("`{}`", t),
+                    Expected::Description(d) => String::from(*d),
+                })
+                .collect::<Vec<_>>();
+
+            diagnostic = diagnostic.with_label(match expected.len() {
+                1 => format!("expected {}", expected[0]),
+                _ => format!("expected one of {}", expected.join(", ")),
+            });
+        }
+
+        let comments = COMMENTS.with(|c| c.borrow().clone());
+        if let Some((keyword, at)) = nearest_keyword(source, start, &comments) {
+            let (line, col) = line_col(source, at);
+            diagnostic = diagnostic.with_note(format!(
+                "nearest `{}` is at line {}, column {}",
+                keyword, line, col
+            ));
+        }
+
+        diagnostic
+    }
+}
+
+type PResult<'a, O> = IResult<&'a str, O, Error<'a>>;
+
+// `#` or `//` up to the end of the line
+fn line_comment(input: &str) -> PResult<'_, &str> {
+    recognize(pair(alt((tag("#"), tag("//"))), not_line_ending))(input)
+}
+
+// `/* */`, which may contain other block comments
+fn block_comment(input: &str) -> PResult<'_, &str> {
+    let (mut rest, _) = tag("/*")(input)?;
+    let mut depth = 1;
+
+    while depth > 0 {
+        if let Some(after) = rest.strip_prefix("/*") {
+            depth += 1;
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("*/") {
+            depth -= 1;
+            rest = after;
+        } else if let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+        } else {
+            return Err(nom::Err::Failure(Error::malformed(
+                input,
+                "unclosed block comment",
+                2,
+                Expected::Description("a matching `*/`"),
+            )));
+        }
+    }
+
+    Ok((rest, &input[..input.len() - rest.len()]))
+}
+
+fn record_comment(span: Span) {
+    COMMENTS.with(|comments| {
+        let mut comments = comments.borrow_mut();
+        if let Err(i) = comments.binary_search_by_key(&span.start, |c| c.start) {
+            comments.insert(i, span);
+        }
+    })
+}
+
+// whitespace and comments, which may appear between any two tokens
+fn trivia(input: &str) -> PResult<'_, ()> {
+    let mut rest = input.trim_start();
+
+    loop {
+        match alt((line_comment, block_comment))(rest) {
+            Ok((after, comment)) => {
+                let start = offset(comment);
+                record_comment(Span::new(start, start + comment.len()));
+                rest = after.trim_start();
+            }
+            Err(nom::Err::Error(_)) => return Ok((rest, ())),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// `input` past any leading trivia, or unchanged if that is malformed so the
+// error is reported by whatever parses it next
+fn skip_trivia(input: &str) -> &str {
+    trivia(input).map_or(input, |(rest, _)| rest)
+}
+
+// tokens skip the trivia before them, never after, so the input left over
+// after a token starts right where the token ends
+fn ws<'a, F, O>(inner: F) -> impl FnMut(&'a str) -> PResult<'a, O>
 where
-    F: Fn(&'a str) -> IResult<&'a str, O, E>,
+    F: Parser<&'a str, O, Error<'a>>,
 {
-    delimited(multispace0, inner, multispace0)
+    preceded(trivia, inner)
 }
 
-fn for_loop(input: &str) -> IResult<&str, Stmt> {
+// replaces whatever `inner` expected with `expected` if it failed without
+// consuming anything, unless it found a malformed token
+fn expect<'a, F, O>(expected: Expected, mut inner: F) -> impl FnMut(&'a str) -> PResult<'a, O>
+where
+    F: Parser<&'a str, O, Error<'a>>,
+{
+    move |input: &'a str| {
+        inner.parse(input).map_err(|e| {
+            e.map(|e| {
+                if e.input.len() == input.len() && e.message.is_none() {
+                    Error {
+                        input,
+                        expected: vec![expected],
+                        message: None,
+                    }
+                } else {
+                    e
+                }
+            })
+        })
+    }
+}
+
+fn symbol<'a>(s: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    expect(Expected::Token(s), tag(s))
+}
+
+fn keyword<'a>(k: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    expect(
+        Expected::Token(k),
+        terminated(tag(k), not(alt((alphanumeric1, tag("_"))))),
+    )
+}
+
+// `=` but not the start of `==`
+fn assign(input: &str) -> PResult<'_, &str> {
+    expect(Expected::Token("="), terminated(tag("="), not(tag("="))))(input)
+}
+
+// the span of whatever `inner` consumed, without leading trivia
+fn spanned<'a, F, O>(mut inner: F) -> impl FnMut(&'a str) -> PResult<'a, (O, Span)>
+where
+    F: Parser<&'a str, O, Error<'a>>,
+{
+    move |input: &'a str| {
+        let (rest, o) = inner.parse(input)?;
+        let start = offset(skip_trivia(input)).min(offset(rest));
+        Ok((rest, (o, Span::new(start, offset(rest)))))
+    }
+}
+
+// what comes between `for i` and `do`
+enum Iteration {
+    Counted(Vec<Expr>),
+    Range {
+        start: Box<Expr>,
+        stop: Box<Expr>,
+        inclusive: bool,
+        step: Option<Box<Expr>>,
+    },
+    Each(Box<Expr>),
+}
+
+// `for i, stop`, `for i, start, stop` and `for i, start, stop, step`, or
+// `for i in start..stop` and `for x in array`
+fn for_loop(input: &str) -> PResult<'_, StmtKind> {
     map(
-        pair(
-            delimited(
-                ws(tag("for")),
-                pair(
-                    ident,
-                    many_m_n(
-                        1,
-                        3,
-                        preceded(ws(tag(",")), map(expr, |mut e| shunting_yard(&mut e))),
-                    ),
+        preceded(
+            ws(keyword("for")),
+            cut(tuple((
+                ws(ident),
+                terminated(
+                    alt((
+                        map(
+                            many_m_n(1, 3, preceded(ws(symbol(",")), map(expression, |e| *e))),
+                            Iteration::Counted,
+                        ),
+                        preceded(ws(keyword("in")), cut(iteration)),
+                    )),
+                    ws(keyword("do")),
                 ),
-                ws(tag("do")),
-            ),
-            terminated(program, ws(tag("end"))),
+                terminated(program, ws(keyword("end"))),
+            ))),
         ),
-        |((ident, exprs), body)| Stmt::For {
-            body,
-            ident: String::from(ident),
-            exprs,
+        |(ident, iteration, body)| {
+            let ident = String::from(ident);
+            match iteration {
+                Iteration::Counted(exprs) => StmtKind::For { body, ident, exprs },
+                Iteration::Range {
+                    start,
+                    stop,
+                    inclusive,
+                    step,
+                } => StmtKind::ForRange {
+                    body,
+                    ident,
+                    start,
+                    stop,
+                    inclusive,
+                    step,
+                },
+                Iteration::Each(array) => StmtKind::ForEach { body, ident, array },
+            }
         },
     )(input)
 }
 
-fn while_loop(input: &str) -> IResult<&str, Stmt> {
+// an array, or a range such as `0..n`, `1..=10` or `0.0..1.0 step 0.25`
+fn iteration(input: &str) -> PResult<'_, Iteration> {
+    let (rest, start) = expression(input)?;
+    let (rest, range) = opt(pair(
+        alt((
+            value(true, ws(symbol("..="))),
+            value(false, ws(symbol(".."))),
+        )),
+        cut(pair(
+            expression,
+            opt(preceded(ws(keyword("step")), cut(expression))),
+        )),
+    ))(rest)?;
+    Ok((
+        rest,
+        match range {
+            Some((inclusive, (stop, step))) => Iteration::Range {
+                start,
+                stop,
+                inclusive,
+                step,
+            },
+            None => Iteration::Each(start),
+        },
+    ))
+}
+
+fn while_loop(input: &str) -> PResult<'_, StmtKind> {
     map(
-        pair(
-            delimited(ws(tag("while")), expr, ws(tag("do"))),
-            terminated(program, ws(tag("end"))),
+        preceded(
+            ws(keyword("while")),
+            cut(pair(
+                terminated(expression, ws(keyword("do"))),
+                terminated(program, ws(keyword("end"))),
+            )),
         ),
-        |(mut expr, body)| Stmt::While {
-            body,
-            expr: shunting_yard(&mut expr),
+        |(expr, body)| StmtKind::While { body, expr },
+    )(input)
+}
+
+fn declaration(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        preceded(
+            ws(keyword("local")),
+            cut(pair(
+                spanned(ws(ident)),
+                opt(preceded(ws(assign), cut(expression))),
+            )),
+        ),
+        |((ident, span), value)| StmtKind::Declaration {
+            name: String::from(ident),
+            value: match value {
+                Some(expr) => expr,
+                None => Box::new(Expr {
+                    kind: ExprKind::Int(0),
+                    span,
+                }),
+            },
         },
     )(input)
 }
 
-fn declaration(input: &str) -> IResult<&str, Stmt> {
-    alt((
-        map(
-            preceded(ws(tag("local")), separated_pair(ident, ws(tag("=")), expr)),
-            |(ident, mut expr)| Stmt::Declaration {
-                name: String::from(ident),
-                value: shunting_yard(&mut expr),
+fn assignment(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        pair(terminated(ws(ident), ws(assign)), cut(expression)),
+        |(ident, value)| StmtKind::Assignment {
+            name: String::from(ident),
+            value,
+        },
+    )(input)
+}
+
+// `a[i] = value`, where `a` can be any expression, even another index, or
+// `p.x = value`
+fn place_assignment(input: &str) -> PResult<'_, StmtKind> {
+    let (rest, target) = terminated(postfix, ws(assign))(input)?;
+    if !matches!(target.kind, ExprKind::Index { .. } | ExprKind::Field { .. }) {
+        return Err(nom::Err::Error(Error::from_error_kind(
+            input,
+            ErrorKind::Tag,
+        )));
+    }
+    let (rest, value) = cut(expression)(rest)?;
+    Ok((
+        rest,
+        match target.kind {
+            ExprKind::Index { array, index } => StmtKind::IndexAssignment {
+                array,
+                index,
+                value,
             },
+            ExprKind::Field { expr, name } => StmtKind::FieldAssignment {
+                target: expr,
+                field: name,
+                value,
+            },
+            _ => unreachable!(),
+        },
+    ))
+}
+
+// `struct Point do x, y: float end`
+fn struct_def(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        preceded(
+            ws(keyword("struct")),
+            cut(pair(
+                terminated(ws(ident), ws(keyword("do"))),
+                terminated(
+                    pair(param, many0(preceded(ws(symbol(",")), cut(param)))),
+                    ws(keyword("end")),
+                ),
+            )),
         ),
-        map(preceded(ws(tag("local")), ident), |s| Stmt::Declaration {
-            name: String::from(s),
-            value: Box::new(Expr::Number(0.0)),
-        }),
-    ))(input)
+        |(name, (first, rest))| StmtKind::Struct {
+            name: String::from(name),
+            fields: std::iter::once(first).chain(rest).collect(),
+        },
+    )(input)
 }
 
-fn assignment(input: &str) -> IResult<&str, Stmt> {
+// `enum Shape do Circle(r), Rect(w, h: float), Empty end`
+fn enum_def(input: &str) -> PResult<'_, StmtKind> {
     map(
-        pair(terminated(ident, ws(tag("="))), expr),
-        |(ident, mut expr)| Stmt::Assignment {
-            name: String::from(ident),
-            value: shunting_yard(&mut expr),
+        preceded(
+            ws(keyword("enum")),
+            cut(pair(
+                terminated(ws(ident), ws(keyword("do"))),
+                terminated(
+                    pair(variant, many0(preceded(ws(symbol(",")), cut(variant)))),
+                    ws(keyword("end")),
+                ),
+            )),
+        ),
+        |(name, (first, rest))| StmtKind::Enum {
+            name: String::from(name),
+            variants: std::iter::once(first).chain(rest).collect(),
         },
     )(input)
 }
 
-fn if_block(input: &str) -> IResult<&str, Stmt> {
+// `Circle(r)`, or `Empty` for a variant without fields
+fn variant(input: &str) -> PResult<'_, (String, Vec<Param>)> {
     map(
         pair(
-            delimited(ws(tag("if")), expr, ws(tag("do"))),
+            ws(ident),
+            opt(preceded(
+                ws(symbol("(")),
+                cut(terminated(
+                    pair(param, many0(preceded(ws(symbol(",")), cut(param)))),
+                    ws(symbol(")")),
+                )),
+            )),
+        ),
+        |(name, fields)| {
+            (
+                String::from(name),
+                fields.map_or(vec![], |(first, rest)| {
+                    std::iter::once(first).chain(rest).collect()
+                }),
+            )
+        },
+    )(input)
+}
+
+// `_`, `true`, `-1`, `Circle(r, _)`, or a name, which matches anything
+fn pattern(input: &str) -> PResult<'_, Pattern> {
+    map(
+        spanned(ws(expect(
+            Expected::Description("a pattern"),
+            alt((
+                value(
+                    PatternKind::Wildcard,
+                    terminated(tag("_"), not(alt((alphanumeric1, tag("_"))))),
+                ),
+                map(
+                    alt((value(true, keyword("true")), value(false, keyword("false")))),
+                    PatternKind::Bool,
+                ),
+                map(pair(opt(tag("-")), int), |(minus, n)| match n {
+                    ExprKind::Int(n) if minus.is_some() => PatternKind::Int(n.wrapping_neg()),
+                    ExprKind::Int(n) => PatternKind::Int(n),
+                    _ => unreachable!(),
+                }),
+                map(
+                    pair(
+                        ident,
+                        opt(preceded(
+                            ws(symbol("(")),
+                            cut(terminated(
+                                pair(pattern, many0(preceded(ws(symbol(",")), cut(pattern)))),
+                                ws(symbol(")")),
+                            )),
+                        )),
+                    ),
+                    |(name, fields)| match fields {
+                        Some((first, rest)) => PatternKind::Constructor {
+                            name: String::from(name),
+                            fields: std::iter::once(first).chain(rest).collect(),
+                        },
+                        None => PatternKind::Binding(String::from(name)),
+                    },
+                ),
+            )),
+        ))),
+        |(kind, span)| Pattern { kind, span },
+    )(input)
+}
+
+// the pattern of an arm and its guard, `Circle(r) if r > 1`
+fn arm_head(input: &str) -> PResult<'_, (Pattern, Option<Expr>)> {
+    pair(pattern, opt(preceded(ws(keyword("if")), cut(expr))))(input)
+}
+
+// `pattern => stmt` or `pattern do ... end`, with an optional `,` after it
+fn stmt_arm(input: &str) -> PResult<'_, Arm<Program>> {
+    map(
+        terminated(
             pair(
-                program,
-                opt(alt((
-                    preceded(ws(tag("else")), map(if_block, |e| vec![e])),
-                    preceded(ws(tag("else")), program),
+                arm_head,
+                cut(alt((
+                    preceded(ws(symbol("=>")), map(stmt, |stmt| vec![stmt])),
+                    preceded(ws(keyword("do")), terminated(program, ws(keyword("end")))),
                 ))),
             ),
+            opt(ws(symbol(","))),
         ),
-        |(mut expr, (body, branch))| Stmt::IfStatement {
-            cond: shunting_yard(&mut expr),
+        |((pattern, guard), body)| Arm {
+            pattern,
+            guard,
             body,
-            branch,
         },
     )(input)
 }
 
-fn if_stmt(input: &str) -> IResult<&str, Stmt> {
-    terminated(if_block, ws(tag("end")))(input)
+fn match_stmt(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        preceded(
+            ws(keyword("match")),
+            cut(pair(
+                terminated(expression, ws(keyword("do"))),
+                terminated(many0(stmt_arm), ws(keyword("end"))),
+            )),
+        ),
+        |(value, arms)| StmtKind::Match { value, arms },
+    )(input)
 }
 
-fn stmt_expr(input: &str) -> IResult<&str, Stmt> {
-    map(expr, |mut e| Stmt::Expression(shunting_yard(&mut e)))(input)
+fn if_block(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        preceded(
+            ws(keyword("if")),
+            cut(pair(
+                terminated(expression, ws(keyword("do"))),
+                pair(
+                    program,
+                    opt(preceded(
+                        ws(keyword("else")),
+                        alt((
+                            map(spanned(if_block), |(kind, span)| vec![Stmt { kind, span }]),
+                            program,
+                        )),
+                    )),
+                ),
+            )),
+        ),
+        |(cond, (body, branch))| StmtKind::IfStatement { cond, body, branch },
+    )(input)
 }
 
-fn function_def(input: &str) -> IResult<&str, Stmt> {
+fn if_stmt(input: &str) -> PResult<'_, StmtKind> {
+    terminated(if_block, ws(keyword("end")))(input)
+}
+
+fn return_stmt(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        preceded(ws(keyword("return")), cut(expression)),
+        StmtKind::Return,
+    )(input)
+}
+
+fn loop_control(input: &str) -> PResult<'_, StmtKind> {
     alt((
-        map(
-            separated_pair(
-                pair(
-                    ident,
-                    delimited(
-                        ws(tag("(")),
-                        opt(pair(ident, many0(preceded(ws(tag(",")), ident)))),
-                        ws(tag(")")),
+        map(ws(keyword("break")), |_| StmtKind::Break),
+        map(ws(keyword("continue")), |_| StmtKind::Continue),
+    ))(input)
+}
+
+fn digits(input: &str) -> PResult<'_, usize> {
+    map_res(digit1, str::parse)(input)
+}
+
+// `[width][.precision][e]` after a `:`, as in `x:8.3` or `x:.2e`
+fn format(input: &str) -> PResult<'_, Format> {
+    let (rest, (width, precision, scientific)) = tuple((
+        opt(digits),
+        opt(preceded(
+            tag("."),
+            cut(expect(Expected::Description("a precision"), digits)),
+        )),
+        opt(keyword("e")),
+    ))(input)?;
+
+    if rest.len() == input.len() {
+        return Err(nom::Err::Error(Error {
+            input,
+            expected: vec![Expected::Description("a format like `8`, `.3` or `10.2e`")],
+            message: None,
+        }));
+    }
+
+    Ok((
+        rest,
+        Format {
+            width: width.unwrap_or(0),
+            precision,
+            scientific: scientific.is_some(),
+        },
+    ))
+}
+
+// an expression with an optional format, as `print` and interpolations take
+fn formatted(input: &str) -> PResult<'_, (Expr, Format)> {
+    pair(
+        expr,
+        map(opt(preceded(ws(symbol(":")), cut(ws(format)))), |f| {
+            f.unwrap_or_default()
+        }),
+    )(input)
+}
+
+fn print_stmt(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        preceded(
+            ws(keyword("print")),
+            cut(delimited(
+                ws(symbol("(")),
+                alt((
+                    map(
+                        pair(formatted, many0(preceded(ws(symbol(",")), cut(formatted)))),
+                        |(arg, args)| {
+                            let mut v = vec![arg];
+                            v.extend(args);
+                            v
+                        },
                     ),
-                ),
-                ws(tag("=")),
-                stmt_expr,
-            ),
-            |((ident, params), expr)| Stmt::FunctionDefinition {
-                name: String::from(ident),
-                args: if let Some((args0, args)) = params {
-                    let mut v = vec![String::from(args0)];
-                    v.extend(args.into_iter().map(String::from));
-                    v
-                } else {
-                    vec![]
-                },
-                body: vec![expr],
-            },
+                    success(vec![]),
+                )),
+                ws(symbol(")")),
+            )),
         ),
+        StmtKind::Print,
+    )(input)
+}
+
+fn stmt_expr(input: &str) -> PResult<'_, StmtKind> {
+    map(expression, StmtKind::Expression)(input)
+}
+
+// `int`, `float`, `bool`, `[int]` for an array of ints, `fn(int) -> float`
+// for a function, or the name of a struct or an enum, which the resolver
+// checks
+fn type_name(input: &str) -> PResult<'_, Type> {
+    if let Ok((rest, _)) = symbol("[")(input) {
+        return map(cut(terminated(ws(type_name), ws(symbol("]")))), |element| {
+            Type::Array(Box::new(element))
+        })(rest);
+    }
+    if let Ok((rest, _)) = keyword("fn")(input) {
+        let params = delimited(
+            ws(symbol("(")),
+            opt(pair(
+                ws(type_name),
+                many0(preceded(ws(symbol(",")), ws(type_name))),
+            )),
+            ws(symbol(")")),
+        );
+        let returns = preceded(ws(symbol("->")), ws(type_name));
+        return map(cut(pair(params, returns)), |(params, returns)| {
+            let params = params.map_or_else(Vec::new, |(first, rest)| {
+                std::iter::once(first).chain(rest).collect()
+            });
+            Type::Function(params, Box::new(returns))
+        })(rest);
+    }
+    let (rest, name) = expect(Expected::Description("a type"), ident)(input)?;
+    Ok((
+        rest,
+        match name {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            _ => Type::Struct(String::from(name)),
+        },
+    ))
+}
+
+fn annotation(input: &str) -> PResult<'_, Annotation> {
+    map(spanned(ws(type_name)), |(ty, span)| Annotation { ty, span })(input)
+}
+
+fn param(input: &str) -> PResult<'_, Param> {
+    map(
+        pair(ws(ident), opt(preceded(ws(symbol(":")), cut(annotation)))),
+        |(name, ty)| Param {
+            name: String::from(name),
+            ty,
+        },
+    )(input)
+}
+
+// `(x, y: int) -> float`, where the types are optional
+fn params(input: &str) -> PResult<'_, (Vec<Param>, Option<Annotation>)> {
+    pair(
         map(
-            pair(
-                pair(
-                    ident,
-                    delimited(
-                        ws(tag("(")),
-                        opt(pair(ident, many0(preceded(ws(tag(",")), ident)))),
-                        ws(tag(")")),
-                    ),
-                ),
-                delimited(ws(tag("do")), program, ws(tag("end"))),
+            delimited(
+                ws(symbol("(")),
+                opt(pair(param, many0(preceded(ws(symbol(",")), param)))),
+                ws(symbol(")")),
             ),
-            |((name, params), body)| Stmt::FunctionDefinition {
-                name: String::from(name),
-                args: if let Some((args0, args)) = params {
-                    let mut v = vec![String::from(args0)];
-                    v.extend(args.into_iter().map(String::from));
+            |params| match params {
+                Some((first, rest)) => {
+                    let mut v = vec![first];
+                    v.extend(rest);
                     v
-                } else {
-                    vec![]
-                },
-                body,
+                }
+                None => vec![],
             },
         ),
-    ))(input)
+        opt(preceded(ws(symbol("->")), cut(annotation))),
+    )(input)
 }
 
-fn float(input: &str) -> IResult<&str, f64> {
-    map_res(
-        alt((recognize(tuple((digit0, tag("."), digit1))), digit1)),
-        |e: &str| e.parse(),
+fn function_def(input: &str) -> PResult<'_, StmtKind> {
+    map(
+        pair(
+            pair(ws(ident), params),
+            alt((
+                preceded(
+                    ws(assign),
+                    cut(map(spanned(stmt_expr), |(kind, span)| {
+                        vec![Stmt { kind, span }]
+                    })),
+                ),
+                preceded(
+                    ws(keyword("do")),
+                    cut(terminated(program, ws(keyword("end")))),
+                ),
+            )),
+        ),
+        |((name, (args, returns)), body)| StmtKind::FunctionDefinition {
+            name: String::from(name),
+            args,
+            returns,
+            body,
+            captures: vec![],
+        },
     )(input)
 }
 
-fn ident(input: &str) -> IResult<&str, &str> {
-    let (i, ident) = recognize(pair(
-        alt((alpha1, tag("_"))),
-        many0_count(alt((alphanumeric1, tag("_")))),
-    ))(input)?;
+// `3` is an int, `3.0` and `.5` are floats
+fn number(input: &str) -> PResult<'_, ExprKind> {
+    alt((
+        map(recognize(tuple((digit0, tag("."), digit1))), |n: &str| {
+            ExprKind::Float(n.parse().unwrap())
+        }),
+        int,
+    ))(input)
+}
+
+fn int(input: &str) -> PResult<'_, ExprKind> {
+    let (rest, n) = digit1(input)?;
+    match n.parse() {
+        Ok(n) => Ok((rest, ExprKind::Int(n))),
+        Err(_) => Err(nom::Err::Failure(Error::malformed(
+            input,
+            "integer literal is too large",
+            n.len(),
+            Expected::Description("at most 9223372036854775807"),
+        ))),
+    }
+}
 
-    match ident {
-        "do" | "end" | "for" | "while" | "if" | "else" | "local" => Err(nom::Err::Error(
-            nom::error::ParseError::from_error_kind(i, nom::error::ErrorKind::Tag),
+// a `"` quoted string on a single line, with `\n`, `\t`, `\r`, `\\` and `\"`
+// escapes, `{{` and `}}` for braces, and `{expr}` or `{expr:format}`
+// interpolations
+fn string(input: &str) -> PResult<'_, Vec<Segment>> {
+    let (mut rest, _) = symbol("\"")(input)?;
+    let mut segments = Vec::new();
+    let mut text = String::new();
+
+    loop {
+        let mut chars = rest.chars();
+        match chars.next() {
+            Some('"') => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(text));
+                }
+                return Ok((&rest[1..], segments));
+            }
+            Some('\\') => {
+                let c = match chars.next() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some(c @ ('\\' | '"')) => c,
+                    c => {
+                        return Err(nom::Err::Failure(Error::malformed(
+                            rest,
+                            "unknown escape sequence",
+                            1 + c.filter(|&c| c != '\n').map_or(0, char::len_utf8),
+                            Expected::Description("one of `\\n`, `\\t`, `\\r`, `\\\\` or `\\\"`"),
+                        )))
+                    }
+                };
+                text.push(c);
+                rest = &rest[2..];
+            }
+            Some(c @ ('{' | '}')) if chars.next() == Some(c) => {
+                text.push(c);
+                rest = &rest[2..];
+            }
+            Some('{') => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                let (after, (expr, format)) =
+                    preceded(tag("{"), cut(terminated(formatted, ws(symbol("}")))))(rest)?;
+                segments.push(Segment::Value(expr, format));
+                rest = after;
+            }
+            Some('}') => {
+                return Err(nom::Err::Failure(Error::malformed(
+                    rest,
+                    "unmatched `}` in a string",
+                    1,
+                    Expected::Description("`}}` for a literal brace"),
+                )))
+            }
+            Some('\n') | None => {
+                return Err(nom::Err::Failure(Error::malformed(
+                    input,
+                    "unterminated string",
+                    1,
+                    Expected::Description("a closing `\"` on the same line"),
+                )))
+            }
+            Some(c) => {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+}
+
+const KEYWORDS: &[&str] = &[
+    "do", "end", "for", "while", "if", "else", "local", "fn", "return", "break", "continue", "and",
+    "or", "xor", "print", "true", "false", "struct", "enum", "match",
+];
+
+fn ident(input: &str) -> PResult<'_, &str> {
+    let (i, ident) = expect(
+        Expected::Description("identifier"),
+        recognize(pair(
+            alt((alpha1, tag("_"))),
+            many0_count(alt((alphanumeric1, tag("_")))),
         )),
-        _ => Ok((i, ident)),
+    )(input)?;
+
+    if KEYWORDS.contains(&ident) {
+        return Err(nom::Err::Error(Error {
+            input,
+            expected: vec![Expected::Description("identifier")],
+            message: None,
+        }));
     }
+
+    Ok((i, ident))
 }
 
-fn unary(input: &str) -> IResult<&str, ExprToken> {
-    alt((
-        map(float, |v| ExprToken::Number(v)),
-        map(
-            pair(ident, delimited(ws(tag("(")), expr_list, ws(tag(")")))),
-            |(i, e)| ExprToken::Call {
-                name: String::from(i),
-                args: e,
-            },
+// `fn(x) => expr` or `fn(x) do ... end`, with the same bodies as named
+// functions
+fn lambda(input: &str) -> PResult<'_, ExprKind> {
+    map(
+        preceded(
+            keyword("fn"),
+            cut(pair(
+                params,
+                alt((
+                    preceded(
+                        ws(symbol("=>")),
+                        map(spanned(stmt_expr), |(kind, span)| vec![Stmt { kind, span }]),
+                    ),
+                    preceded(ws(keyword("do")), terminated(program, ws(keyword("end")))),
+                )),
+            )),
         ),
-        map(ident, |s| ExprToken::Ident(String::from(s))),
-    ))(input)
+        |((args, returns), body)| ExprKind::Lambda {
+            name: String::from("fn"),
+            args,
+            returns,
+            body,
+            captures: vec![],
+        },
+    )(input)
 }
 
-fn term(input: &str) -> IResult<&str, Vec<ExprToken>> {
-    alt((
-        map(
-            delimited(ws(tag("(")), expr, ws(tag(")"))),
-            |mut e: Vec<ExprToken>| {
-                e.insert(0, ExprToken::LParen);
-                e.push(ExprToken::RParen);
-                e
-            },
+// `match value do pattern => expr, ... end`, where each arm gives the value
+fn match_expr(input: &str) -> PResult<'_, ExprKind> {
+    map(
+        preceded(
+            keyword("match"),
+            cut(pair(
+                terminated(expression, ws(keyword("do"))),
+                terminated(many0(expr_arm), ws(keyword("end"))),
+            )),
         ),
-        map(unary, |u| vec![u]),
-    ))(input)
+        |(value, arms)| ExprKind::Match { value, arms },
+    )(input)
 }
 
-fn op(input: &str) -> IResult<&str, ExprToken> {
+fn expr_arm(input: &str) -> PResult<'_, Arm<Expr>> {
     map(
+        terminated(
+            pair(arm_head, cut(preceded(ws(symbol("=>")), expr))),
+            opt(ws(symbol(","))),
+        ),
+        |((pattern, guard), body)| Arm {
+            pattern,
+            guard,
+            body,
+        },
+    )(input)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+type Binary = fn(Box<Expr>, Box<Expr>) -> ExprKind;
+type Unary = fn(Box<Expr>) -> ExprKind;
+
+// Every binary operator with its binding power, higher binding tighter. This
+// is the only place precedence and associativity are decided. Operators are
+// tried in order, so `<=` has to come before `<`.
+const INFIX: &[(&str, u8, Assoc, Binary)] = &[
+    ("or", 1, Assoc::Left, ExprKind::Or),
+    ("||", 1, Assoc::Left, ExprKind::Or),
+    ("xor", 2, Assoc::Left, ExprKind::Xor),
+    ("and", 3, Assoc::Left, ExprKind::And),
+    ("&&", 3, Assoc::Left, ExprKind::And),
+    ("==", 4, Assoc::Left, ExprKind::Eq),
+    ("!=", 4, Assoc::Left, ExprKind::Neq),
+    ("<=", 5, Assoc::Left, ExprKind::Leq),
+    (">=", 5, Assoc::Left, ExprKind::Geq),
+    ("<", 5, Assoc::Left, ExprKind::Lt),
+    (">", 5, Assoc::Left, ExprKind::Gt),
+    ("+", 6, Assoc::Left, ExprKind::Add),
+    ("-", 6, Assoc::Left, ExprKind::Sub),
+    ("*", 7, Assoc::Left, ExprKind::Mul),
+    ("/", 7, Assoc::Left, ExprKind::Div),
+    ("%", 7, Assoc::Left, ExprKind::Mod),
+    ("^", 9, Assoc::Right, ExprKind::Pow),
+];
+
+// prefix operators bind tighter than everything but `^`, so `-2^2` is
+// `-(2^2)`, and calls bind tightest of all
+const PREFIX: &[(&str, Unary)] = &[
+    ("-", ExprKind::Neg),
+    ("+", ExprKind::Pos),
+    ("!", ExprKind::Not),
+];
+const PREFIX_POWER: u8 = 8;
+
+// an operator, as a whole word if it is spelled with letters
+fn operator<'a>(op: &'static str) -> impl FnMut(&'a str) -> PResult<'a, &'a str> {
+    move |input: &'a str| {
+        if op.starts_with(char::is_alphabetic) {
+            keyword(op)(input)
+        } else {
+            symbol(op)(input)
+        }
+    }
+}
+
+fn infix(input: &str) -> PResult<'_, (&'static str, u8, Assoc, Binary)> {
+    for &(op, power, assoc, build) in INFIX {
+        if let Ok((rest, _)) = ws(operator(op))(input) {
+            return Ok((rest, (op, power, assoc, build)));
+        }
+    }
+
+    Err(nom::Err::Error(Error::from_error_kind(
+        input,
+        ErrorKind::Tag,
+    )))
+}
+
+// whether the trivia before the next token contains a line break
+fn line_break(input: &str) -> bool {
+    input[..input.len() - skip_trivia(input).len()].contains('\n')
+}
+
+fn primary(input: &str) -> PResult<'_, Expr> {
+    expect(
+        Expected::Description("expression"),
         alt((
-            tag("+"),
-            tag("-"),
-            tag("*"),
-            tag("/"),
-            tag("^"),
-            tag("%"),
-            tag("<="),
-            tag(">="),
-            tag("<"),
-            tag(">"),
-            tag("=="),
-            tag("!="),
+            preceded(symbol("("), cut(terminated(expr, ws(symbol(")"))))),
+            map(spanned(number), |(kind, span)| Expr { kind, span }),
+            map(
+                spanned(alt((
+                    value(true, keyword("true")),
+                    value(false, keyword("false")),
+                ))),
+                |(b, span)| Expr {
+                    kind: ExprKind::Bool(b),
+                    span,
+                },
+            ),
+            map(spanned(string), |(s, span)| Expr {
+                kind: ExprKind::Str(s),
+                span,
+            }),
+            map(spanned(lambda), |(kind, span)| Expr { kind, span }),
+            map(spanned(match_expr), |(kind, span)| Expr { kind, span }),
+            map(
+                spanned(preceded(
+                    symbol("["),
+                    cut(terminated(expr_list, ws(symbol("]")))),
+                )),
+                |(elements, span)| Expr {
+                    kind: ExprKind::Array(elements),
+                    span,
+                },
+            ),
+            map(spanned(record), |(fields, span)| Expr {
+                kind: ExprKind::Record(fields),
+                span,
+            }),
+            map(spanned(ident), |(name, span)| Expr {
+                kind: ExprKind::Ident(String::from(name)),
+                span,
+            }),
         )),
-        |op| {
-            use ExprToken::*;
-            match op {
-                "+" => Add,
-                "-" => Sub,
-                "*" => Mul,
-                "/" => Div,
-                "^" => Pow,
-                "%" => Mod,
-                "<=" => Leq,
-                ">=" => Geq,
-                "<" => Lt,
-                ">" => Gt,
-                "==" => Eq,
-                "!=" => Neq,
-                _ => unreachable!(),
-            }
-        },
     )(input)
 }
 
-fn expr(input: &str) -> IResult<&str, Vec<ExprToken>> {
+// `{x: 1, y: 2}`
+fn record(input: &str) -> PResult<'_, Vec<(String, Expr)>> {
+    preceded(
+        symbol("{"),
+        cut(terminated(
+            map(
+                pair(
+                    record_field,
+                    many0(preceded(ws(symbol(",")), cut(record_field))),
+                ),
+                |(first, rest)| std::iter::once(first).chain(rest).collect(),
+            ),
+            ws(symbol("}")),
+        )),
+    )(input)
+}
+
+fn record_field(input: &str) -> PResult<'_, (String, Expr)> {
     map(
-        pair(ws(term), many0(pair(ws(op), ws(term)))),
-        |(mut e, l)| {
-            for (op, e2) in l {
-                e.push(op);
-                e.extend(e2.into_iter());
-            }
-            e
-        },
+        pair(terminated(ws(ident), cut(ws(symbol(":")))), cut(expr)),
+        |(name, value)| (String::from(name), value),
     )(input)
 }
 
-fn expr_list(input: &str) -> IResult<&str, Vec<Vec<ExprToken>>> {
+// calls, of a function by name or of any other expression, indexing and
+// fields; a `(`, `[` or `.` at the start of a line begins a new statement
+// instead
+fn postfix(input: &str) -> PResult<'_, Expr> {
+    let (mut rest, mut expr) = ws(primary)(input)?;
+
+    while !line_break(rest) {
+        // `..` is a range rather than a field
+        match spanned(ws(preceded(
+            terminated(symbol("."), not(tag("."))),
+            cut(ws(ident)),
+        )))(rest)
+        {
+            Ok((after, (name, span))) => {
+                let span = expr.span.to(span);
+                expr = Expr {
+                    kind: ExprKind::Field {
+                        expr: Box::new(expr),
+                        name: String::from(name),
+                    },
+                    span,
+                };
+                rest = after;
+                continue;
+            }
+            Err(nom::Err::Error(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        match spanned(ws(preceded(
+            symbol("["),
+            cut(terminated(expression, ws(symbol("]")))),
+        )))(rest)
+        {
+            Ok((after, (index, span))) => {
+                let array = expr.span;
+                expr = Expr {
+                    kind: ExprKind::Index {
+                        array: Box::new(expr),
+                        index,
+                    },
+                    span: array.to(span),
+                };
+                rest = after;
+                continue;
+            }
+            Err(nom::Err::Error(_)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let (after, (args, span)) = match spanned(ws(preceded(
+            symbol("("),
+            cut(terminated(expr_list, ws(symbol(")")))),
+        )))(rest)
+        {
+            Ok(call) => call,
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+
+        let callee = expr.span;
+        expr = Expr {
+            kind: match expr.kind {
+                ExprKind::Ident(name) => ExprKind::Call { name, args },
+                kind => ExprKind::CallValue {
+                    callee: Box::new(Expr { kind, span: callee }),
+                    args,
+                },
+            },
+            span: callee.to(span),
+        };
+        rest = after;
+    }
+
+    Ok((rest, expr))
+}
+
+fn prefix(input: &str) -> PResult<'_, Expr> {
+    for &(op, build) in PREFIX {
+        if let Ok((rest, (_, span))) = spanned(ws(operator(op)))(input) {
+            let (rest, operand) = cut(|i| expr_bp(i, PREFIX_POWER))(rest)?;
+            let span = span.to(operand.span);
+            return Ok((
+                rest,
+                Expr {
+                    kind: build(Box::new(operand)),
+                    span,
+                },
+            ));
+        }
+    }
+
+    postfix(input)
+}
+
+// Pratt parsing: an operand, then every operator binding at least as tight as
+// `min` along with its right hand side. A left associative operator binds its
+// right hand side one step tighter, so `a - b - c` is `(a - b) - c`.
+fn expr_bp(input: &str, min: u8) -> PResult<'_, Expr> {
+    let (mut rest, mut lhs) = prefix(input)?;
+
+    loop {
+        let (after, (op, power, assoc, build)) = match infix(rest) {
+            Ok(found) => found,
+            Err(nom::Err::Error(_)) => break,
+            Err(e) => return Err(e),
+        };
+
+        // a `+` or `-` at the start of a line begins a new statement rather
+        // than continuing the expression on the line before, so `x` followed
+        // by `-y` on the next line is two statements
+        if power < min || (matches!(op, "+" | "-") && line_break(rest)) {
+            break;
+        }
+
+        let next = match assoc {
+            Assoc::Left => power + 1,
+            Assoc::Right => power,
+        };
+        let (after, rhs) = cut(|i| expr_bp(i, next))(after)?;
+
+        let span = lhs.span.to(rhs.span);
+        lhs = Expr {
+            kind: build(Box::new(lhs), Box::new(rhs)),
+            span,
+        };
+        rest = after;
+    }
+
+    Ok((rest, lhs))
+}
+
+fn expr(input: &str) -> PResult<'_, Expr> {
+    expr_bp(input, 0)
+}
+
+fn expression(input: &str) -> PResult<'_, Box<Expr>> {
+    map(expr, Box::new)(input)
+}
+
+fn expr_list(input: &str) -> PResult<'_, Vec<Expr>> {
     alt((
         map(
-            pair(expr, many0(preceded(ws(tag(",")), expr))),
+            pair(expr, many0(preceded(ws(symbol(",")), cut(expr)))),
             |(e, el)| {
                 let mut v = vec![e];
-                for e in el {
-                    v.push(e);
-                }
+                v.extend(el);
                 v
             },
         ),
@@ -282,30 +1257,791 @@ fn expr_list(input: &str) -> IResult<&str, Vec<Vec<ExprToken>>> {
     ))(input)
 }
 
-fn stmt(input: &str) -> IResult<&str, Stmt> {
-    alt((
-        for_loop,
-        while_loop,
-        if_stmt,
-        function_def,
-        assignment,
-        declaration,
-        stmt_expr,
-    ))(input)
+fn stmt(input: &str) -> PResult<'_, Stmt> {
+    map(
+        preceded(
+            trivia,
+            spanned(expect(
+                Expected::Description("statement"),
+                alt((
+                    for_loop,
+                    while_loop,
+                    if_stmt,
+                    function_def,
+                    return_stmt,
+                    loop_control,
+                    print_stmt,
+                    struct_def,
+                    enum_def,
+                    match_stmt,
+                    assignment,
+                    place_assignment,
+                    declaration,
+                    stmt_expr,
+                )),
+            )),
+        ),
+        |(kind, span)| Stmt { kind, span },
+    )(input)
 }
 
-pub fn program(input: &str) -> IResult<&str, Program> {
+fn program(input: &str) -> PResult<'_, Program> {
     many0(stmt)(input)
 }
 
-pub fn parse(input: &str) -> Program {
-    let p = program(input);
+// skips past the next newline or `end`, whichever comes first, so parsing can
+// resume after a broken statement; newlines and `end`s inside comments don't
+// count
+fn recover(input: &str) -> &str {
+    let mut rest = input;
 
-    match p {
-        Ok(v) => v.1,
-        Err(_) => {
-            eprintln!("failed to parse input");
-            std::process::exit(1)
+    while let Some(c) = rest.chars().next() {
+        if let Ok((after, _)) = alt((line_comment, block_comment))(rest) {
+            rest = after;
+            continue;
+        }
+        if c == '\n' {
+            return &rest[1..];
         }
+        if let Ok((after, _)) = keyword("end")(rest) {
+            return after;
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    rest
+}
+
+// the program along with the span of every comment in it, in source order,
+// so tools that rewrite or document the source can put them back
+pub fn parse(input: &str) -> Result<(Program, Vec<Span>), Vec<Diagnostic>> {
+    SOURCE.with(|s| s.set(input.as_ptr() as usize));
+    COMMENTS.with(|c| c.borrow_mut().clear());
+
+    let mut program = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = skip_trivia(input);
+
+    while !rest.is_empty() {
+        // a block that failed to parse usually leaves its `else`/`end` behind
+        if !errors.is_empty() && alt((keyword("end"), keyword("else")))(rest).is_ok() {
+            rest = skip_trivia(recover(rest));
+            continue;
+        }
+
+        match stmt(rest) {
+            Ok((after, stmt)) => {
+                program.push(stmt);
+                rest = after;
+            }
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                let failed_at = e.input;
+                let mut diagnostic = e.into_diagnostic(input);
+
+                if failed_at.len() != rest.len() && !failed_at.starts_with("/*") {
+                    let (line, col) = line_col(input, offset(rest));
+                    diagnostic = diagnostic.with_note(format!(
+                        "in the statement starting at line {}, column {}",
+                        line, col
+                    ));
+                }
+
+                errors.push(diagnostic);
+                rest = recover(failed_at);
+            }
+            Err(nom::Err::Incomplete(_)) => unreachable!(),
+        }
+
+        rest = skip_trivia(rest);
+    }
+
+    if errors.is_empty() {
+        Ok((program, COMMENTS.with(|c| c.take())))
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(expr: &Expr) -> String {
+        let binary =
+            |op: &str, lhs: &Expr, rhs: &Expr| format!("({} {} {})", op, show(lhs), show(rhs));
+        let list = |args: &[Expr]| args.iter().map(show).collect::<Vec<_>>().join(" ");
+        match &expr.kind {
+            ExprKind::Add(l, r) => binary("+", l, r),
+            ExprKind::Sub(l, r) => binary("-", l, r),
+            ExprKind::Mul(l, r) => binary("*", l, r),
+            ExprKind::Div(l, r) => binary("/", l, r),
+            ExprKind::Pow(l, r) => binary("^", l, r),
+            ExprKind::Mod(l, r) => binary("%", l, r),
+            ExprKind::Leq(l, r) => binary("<=", l, r),
+            ExprKind::Geq(l, r) => binary(">=", l, r),
+            ExprKind::Lt(l, r) => binary("<", l, r),
+            ExprKind::Gt(l, r) => binary(">", l, r),
+            ExprKind::Eq(l, r) => binary("==", l, r),
+            ExprKind::Neq(l, r) => binary("!=", l, r),
+            ExprKind::And(l, r) => binary("and", l, r),
+            ExprKind::Or(l, r) => binary("or", l, r),
+            ExprKind::Xor(l, r) => binary("xor", l, r),
+            ExprKind::Neg(e) => format!("(neg {})", show(e)),
+            ExprKind::Pos(e) => format!("(pos {})", show(e)),
+            ExprKind::Not(e) => format!("(! {})", show(e)),
+            ExprKind::Int(n) => n.to_string(),
+            ExprKind::Float(n) => format!("{:?}", n),
+            ExprKind::Bool(b) => b.to_string(),
+            ExprKind::Str(segments) => segments
+                .iter()
+                .map(|segment| match segment {
+                    Segment::Text(text) => format!("{:?}", text),
+                    Segment::Value(expr, _) => format!("{{{}}}", show(expr)),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            ExprKind::Ident(name) => name.clone(),
+            ExprKind::Call { name, args } => format!("({} {})", name, list(args)),
+            ExprKind::CallValue { callee, args } => {
+                format!("(call {} {})", show(callee), list(args))
+            }
+            ExprKind::Lambda { args, .. } => {
+                let args = args
+                    .iter()
+                    .map(|arg| match &arg.ty {
+                        Some(annotation) => format!("{}:{}", arg.name, annotation.ty),
+                        None => arg.name.clone(),
+                    })
+                    .collect::<Vec<_>>();
+                format!("(fn {})", args.join(" "))
+            }
+            ExprKind::Function(name) => name.clone(),
+            ExprKind::Array(elements) => format!("[{}]", list(elements)),
+            ExprKind::Index { array, index } => binary("index", array, index),
+            ExprKind::Record(fields) => {
+                let fields = fields
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, show(value)))
+                    .collect::<Vec<_>>();
+                format!("{{{}}}", fields.join(" "))
+            }
+            ExprKind::Field { expr, name } => format!("(. {} {})", show(expr), name),
+            ExprKind::Match { value, arms } => {
+                let arms = arms
+                    .iter()
+                    .map(|arm| match &arm.guard {
+                        Some(guard) => format!(
+                            "({} if {} => {})",
+                            show_pattern(&arm.pattern),
+                            show(guard),
+                            show(&arm.body)
+                        ),
+                        None => format!("({} => {})", show_pattern(&arm.pattern), show(&arm.body)),
+                    })
+                    .collect::<Vec<_>>();
+                format!("(match {} {})", show(value), arms.join(" "))
+            }
+            ExprKind::NewArray { .. }
+            | ExprKind::Len(_)
+            | ExprKind::Struct { .. }
+            | ExprKind::Variant { .. } => {
+                unreachable!("only the resolver makes these")
+            }
+        }
+    }
+
+    fn show_pattern(pattern: &Pattern) -> String {
+        match &pattern.kind {
+            PatternKind::Wildcard => String::from("_"),
+            PatternKind::Binding(name) => name.clone(),
+            PatternKind::Int(n) => n.to_string(),
+            PatternKind::Bool(b) => b.to_string(),
+            PatternKind::Constructor { name, fields } => {
+                let fields = fields.iter().map(show_pattern).collect::<Vec<_>>();
+                format!("({} {})", name, fields.join(" "))
+            }
+        }
+    }
+
+    fn parse_expr(source: &str) -> Expr {
+        SOURCE.with(|s| s.set(source.as_ptr() as usize));
+        match expr(source) {
+            Ok(("", expr)) => expr,
+            Ok((rest, expr)) => panic!("{} parsed as {} up to {:?}", source, show(&expr), rest),
+            Err(e) => panic!("{} failed to parse: {:?}", source, e),
+        }
+    }
+
+    fn tree(source: &str) -> String {
+        show(&parse_expr(source))
+    }
+
+    fn error(source: &str) -> String {
+        match parse(source) {
+            Ok((program, _)) => panic!("{} parsed as {:?}", source, program),
+            Err(errors) => {
+                let e = &errors[0];
+                format!("{}: {}", e.message, e.label.as_deref().unwrap_or(""))
+            }
+        }
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(tree("1"), "1");
+        assert_eq!(tree("x"), "x");
+        assert_eq!(tree("((x))"), "x");
+        assert_eq!(tree("f(1 + 2, y)"), "(f (+ 1 2) y)");
+        assert_eq!(tree("f()"), "(f )");
+    }
+
+    #[test]
+    fn numbers() {
+        let kind = |source| parse_expr(source).kind;
+        assert!(matches!(kind("7"), ExprKind::Int(7)));
+        assert!(matches!(
+            kind("9223372036854775807"),
+            ExprKind::Int(i64::MAX)
+        ));
+        assert!(matches!(kind("7.0"), ExprKind::Float(n) if n == 7.0));
+        assert!(matches!(kind(".5"), ExprKind::Float(n) if n == 0.5));
+        assert_eq!(
+            error("9223372036854775808"),
+            "integer literal is too large: expected at most 9223372036854775807"
+        );
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(tree("true"), "true");
+        assert_eq!(tree("!true and x == false"), "(and (! true) (== x false))");
+        assert_eq!(tree("trueish"), "trueish");
+        assert_eq!(
+            error("local false = 1"),
+            "unexpected `false`: expected identifier"
+        );
+    }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(tree("10 - 3 - 2"), "(- (- 10 3) 2)");
+        assert_eq!(tree("8 / 4 / 2"), "(/ (/ 8 4) 2)");
+        assert_eq!(tree("7 - 2 + 1"), "(+ (- 7 2) 1)");
+        assert_eq!(tree("100 / 10 * 2"), "(* (/ 100 10) 2)");
+        assert_eq!(tree("9 % 5 * 2"), "(* (% 9 5) 2)");
+        assert_eq!(tree("a == b != c"), "(!= (== a b) c)");
+        assert_eq!(tree("a < b <= c"), "(<= (< a b) c)");
+        assert_eq!(tree("a and b && c"), "(and (and a b) c)");
+        assert_eq!(tree("a or b || c"), "(or (or a b) c)");
+        assert_eq!(tree("a xor b xor c"), "(xor (xor a b) c)");
+    }
+
+    #[test]
+    fn right_associative() {
+        assert_eq!(tree("2 ^ 3 ^ 2"), "(^ 2 (^ 3 2))");
+        assert_eq!(tree("a ^ b ^ c ^ d"), "(^ a (^ b (^ c d)))");
+        assert_eq!(tree("- -x"), "(neg (neg x))");
+        assert_eq!(tree("!-x"), "(! (neg x))");
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(tree("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(tree("1 * 2 + 3"), "(+ (* 1 2) 3)");
+        assert_eq!(tree("2 * 3 ^ 2"), "(* 2 (^ 3 2))");
+        assert_eq!(tree("2 ^ 3 * 2"), "(* (^ 2 3) 2)");
+        assert_eq!(tree("a + b < c - d"), "(< (+ a b) (- c d))");
+        assert_eq!(tree("a < b == c > d"), "(== (< a b) (> c d))");
+        assert_eq!(tree("a == b and c != d"), "(and (== a b) (!= c d))");
+        assert_eq!(tree("a or b and c"), "(or a (and b c))");
+        assert_eq!(tree("a and b or c"), "(or (and a b) c)");
+        assert_eq!(tree("a or b xor c and d"), "(or a (xor b (and c d)))");
+        assert_eq!(tree("1 - 2 * 3 + 4"), "(+ (- 1 (* 2 3)) 4)");
+    }
+
+    #[test]
+    fn prefix_operators() {
+        assert_eq!(tree("-2 ^ 2"), "(neg (^ 2 2))");
+        assert_eq!(tree("2 ^ -1"), "(^ 2 (neg 1))");
+        assert_eq!(tree("-a * b"), "(* (neg a) b)");
+        assert_eq!(tree("a * -b"), "(* a (neg b))");
+        assert_eq!(tree("a - -b"), "(- a (neg b))");
+        assert_eq!(tree("+a + b"), "(+ (pos a) b)");
+        assert_eq!(tree("!a == b"), "(== (! a) b)");
+        assert_eq!(tree("!(a == b)"), "(! (== a b))");
+        assert_eq!(tree("-2 ^ -2 ^ 2"), "(neg (^ 2 (neg (^ 2 2))))");
+        assert_eq!(tree("-f(x)"), "(neg (f x))");
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(tree("(1 + 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(tree("10 - (3 - 2)"), "(- 10 (- 3 2))");
+        assert_eq!(tree("(2 ^ 3) ^ 2"), "(^ (^ 2 3) 2)");
+        assert_eq!(tree("(a or b) and c"), "(and (or a b) c)");
+        assert_eq!(tree("-(2 ^ 2)"), "(neg (^ 2 2))");
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(tree("f(1)(2)"), "(call (f 1) 2)");
+        assert_eq!(tree("(fn(x) => x)(3)"), "(call (fn x) 3)");
+        assert_eq!(tree("fn(x) => x * 2"), "(fn x)");
+        assert_eq!(tree("f(g(x), -y) ^ 2"), "(^ (f (g x) (neg y)) 2)");
+    }
+
+    #[test]
+    fn spans() {
+        let expr = parse_expr("10 - 3 - 2");
+        assert_eq!(expr.span, Span::new(0, 10));
+        match expr.kind {
+            ExprKind::Sub(lhs, rhs) => {
+                assert_eq!(lhs.span, Span::new(0, 6));
+                assert_eq!(rhs.span, Span::new(9, 10));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert_eq!(parse_expr("-x").span, Span::new(0, 2));
+        assert_eq!(parse_expr("f(1)(2)").span, Span::new(0, 7));
+    }
+
+    #[test]
+    fn statements_on_new_lines() {
+        assert_eq!(parse("x\n-y").unwrap().0.len(), 2);
+        assert_eq!(parse("x\n(y)").unwrap().0.len(), 2);
+        assert_eq!(parse("x -\ny").unwrap().0.len(), 1);
+        assert_eq!(parse("x\nand y").unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            error("local x = 1 +"),
+            "unexpected end of input: expected expression"
+        );
+        assert_eq!(error("(1 + 2"), "unexpected end of input: expected `)`");
+        assert_eq!(error("1 + 2)"), "unexpected `)`: expected statement");
+        assert_eq!(error("()"), "unexpected `)`: expected expression");
+        assert_eq!(error("1 * / 2"), "unexpected `/`: expected expression");
+        assert_eq!(error("f(1 2)"), "unexpected `2`: expected `)`");
+        assert_eq!(
+            error("local y = -"),
+            "unexpected end of input: expected expression"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let source = "# one\nlocal x = 1 // two\nx /* three /* four */ */ + 1\n";
+        let (program, comments) = parse(source).unwrap();
+        assert_eq!(program.len(), 2);
+
+        let text = comments
+            .iter()
+            .map(|c| &source[c.start..c.end])
+            .collect::<Vec<_>>();
+        assert_eq!(text, ["# one", "// two", "/* three /* four */ */"]);
+
+        assert_eq!(program[1].span, Span::new(25, 53));
+        assert_eq!(tree("1 /* a */ + /* b */ 2"), "(+ 1 2)");
+        assert_eq!(tree("f(/* a */ 1, # b\n 2)"), "(f 1 2)");
+        assert_eq!(parse("x # comment\n-y").unwrap().0.len(), 2);
+        assert_eq!(parse("x - # comment\ny").unwrap().0.len(), 1);
+        assert_eq!(parse("/* */").unwrap().0.len(), 0);
+    }
+
+    #[test]
+    fn unclosed_comment() {
+        assert_eq!(
+            error("local x = 1\n/* a /* b */\nx"),
+            "unclosed block comment: expected a matching `*/`"
+        );
+        assert_eq!(
+            error("f(x) do\n  # end\n  x +\nend"),
+            "unexpected `end`: expected expression"
+        );
+    }
+
+    fn print_args(source: &str) -> Vec<(String, Format)> {
+        match parse(source).unwrap().0.remove(0).kind {
+            StmtKind::Print(args) => args.iter().map(|(e, f)| (show(e), *f)).collect(),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            print_args(r#"print("a\tb \"c\" \\ # /* d", "")"#),
+            [
+                (String::from(r#""a\tb \"c\" \\ # /* d""#), Format::default()),
+                (String::new(), Format::default()),
+            ]
+        );
+        assert_eq!(
+            error(r#"print("a\q")"#),
+            r#"unknown escape sequence: expected one of `\n`, `\t`, `\r`, `\\` or `\"`"#
+        );
+        assert_eq!(
+            error("print(\"a\n)"),
+            "unterminated string: expected a closing `\"` on the same line"
+        );
+    }
+
+    #[test]
+    fn print_formats() {
+        let format = |width, precision, scientific| Format {
+            width,
+            precision,
+            scientific,
+        };
+        assert_eq!(
+            print_args("print(x, x:8, x:.3, x:10.2, x:e, x : .2e, f(x) + 1:12.4e)"),
+            [
+                (String::from("x"), format(0, None, false)),
+                (String::from("x"), format(8, None, false)),
+                (String::from("x"), format(0, Some(3), false)),
+                (String::from("x"), format(10, Some(2), false)),
+                (String::from("x"), format(0, None, true)),
+                (String::from("x"), format(0, Some(2), true)),
+                (String::from("(+ (f x) 1)"), format(12, Some(4), true)),
+            ]
+        );
+        assert_eq!(print_args("print()"), []);
+        assert_eq!(
+            error("print(x:)"),
+            "unexpected `)`: expected a format like `8`, `.3` or `10.2e`"
+        );
+        assert_eq!(error("print(x:.)"), "unexpected `)`: expected a precision");
+    }
+
+    #[test]
+    fn interpolation() {
+        let segments = |source: &str| match parse_expr(source).kind {
+            ExprKind::Str(segments) => segments,
+            other => panic!("{:?}", other),
+        };
+
+        assert_eq!(
+            tree(r#""total = {total:.3}, {f(x) + 1} {{ok}}""#),
+            r#""total = " {total} ", " {(+ (f x) 1)} " {ok}""#
+        );
+        assert_eq!(tree(r#""{ a }{b}""#), "{a} {b}");
+
+        match &segments(r#""{x:8.2e}""#)[0] {
+            Segment::Value(expr, format) => {
+                assert_eq!(expr.span, Span::new(2, 3));
+                assert_eq!(
+                    *format,
+                    Format {
+                        width: 8,
+                        precision: Some(2),
+                        scientific: true
+                    }
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+
+        assert_eq!(error(r#"print("{x")"#), "unexpected `\"`: expected `}`");
+        assert_eq!(
+            error(r#"print("{}")"#),
+            "unexpected `}`: expected expression"
+        );
+        assert_eq!(
+            error(r#"print("a } b")"#),
+            "unmatched `}` in a string: expected `}}` for a literal brace"
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        let program = parse("f(x: int, y, z :float) -> float = x\n").unwrap().0;
+        let (args, returns) = match &program[0].kind {
+            StmtKind::FunctionDefinition { args, returns, .. } => (args, returns),
+            kind => panic!("parsed as {:?}", kind),
+        };
+        let types = args
+            .iter()
+            .map(|arg| (arg.name.as_str(), arg.ty.as_ref().map(|a| a.ty.clone())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                ("x", Some(Type::Int)),
+                ("y", None),
+                ("z", Some(Type::Float))
+            ]
+        );
+        assert_eq!(
+            returns.as_ref().map(|a| (a.ty.clone(), a.span)),
+            Some((Type::Float, Span::new(26, 31)))
+        );
+
+        assert_eq!(tree("fn(n: int) -> int => n"), "(fn n:int)");
+        assert_eq!(error("f(x: 1) = x"), "unexpected `1`: expected a type");
+        assert_eq!(error("f(x:) = x"), "unexpected `)`: expected a type");
+        assert_eq!(error("f(x) -> = x"), "unexpected `=`: expected a type");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(tree("[1, 2 + 3]"), "[1 (+ 2 3)]");
+        assert_eq!(tree("[]"), "[]");
+        assert_eq!(tree("[[1], [x]]"), "[[1] [x]]");
+        assert_eq!(tree("a[i][j + 1]"), "(index (index a i) (+ j 1))");
+        assert_eq!(tree("f(x)[0] * 2"), "(* (index (f x) 0) 2)");
+        assert_eq!(tree("-a[0] ^ 2"), "(neg (^ (index a 0) 2))");
+        assert_eq!(tree("[1, 2][0]"), "(index [1 2] 0)");
+        assert_eq!(parse("x\n[1]").unwrap().0.len(), 2);
+
+        let program = parse("a[i][0] = 1\nb = [a[0]]\n").unwrap().0;
+        match &program[0].kind {
+            StmtKind::IndexAssignment {
+                array,
+                index,
+                value,
+            } => assert_eq!(
+                (show(array), show(index), show(value)),
+                (
+                    String::from("(index a i)"),
+                    String::from("0"),
+                    String::from("1")
+                )
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+        assert!(matches!(program[1].kind, StmtKind::Assignment { .. }));
+
+        let program = parse("f(xs: [[float]]) = xs\n").unwrap().0;
+        match &program[0].kind {
+            StmtKind::FunctionDefinition { args, .. } => assert_eq!(
+                args[0].ty.as_ref().map(|a| a.ty.to_string()),
+                Some(String::from("[[float]]"))
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+
+        assert_eq!(error("[1, 2"), "unexpected end of input: expected `]`");
+        assert_eq!(error("a[]"), "unexpected `]`: expected expression");
+        assert_eq!(
+            error("f(x) = 1\nf(1) = 2"),
+            "unexpected `=`: expected statement"
+        );
+    }
+
+    #[test]
+    fn for_in() {
+        let header = |source: &str| match &parse(source).unwrap().0[0].kind {
+            StmtKind::ForRange {
+                ident,
+                start,
+                stop,
+                inclusive,
+                step,
+                ..
+            } => format!(
+                "{} {} {} {} {}",
+                ident,
+                show(start),
+                if *inclusive { "..=" } else { ".." },
+                show(stop),
+                step.as_ref().map_or(String::new(), |step| show(step))
+            ),
+            StmtKind::ForEach { ident, array, .. } => format!("{} each {}", ident, show(array)),
+            kind => panic!("{} parsed as {:?}", source, kind),
+        };
+        assert_eq!(header("for i in 1..10 do end"), "i 1 .. 10 ");
+        assert_eq!(header("for i in 1..=n - 1 do end"), "i 1 ..= (- n 1) ");
+        assert_eq!(header("for i in 0..n step 2 do end"), "i 0 .. n 2");
+        assert_eq!(
+            header("for x in 0.5..1.5 step .25 do end"),
+            "x 0.5 .. 1.5 0.25"
+        );
+        assert_eq!(
+            header("for i in r.lo..r.hi do end"),
+            "i (. r lo) .. (. r hi) "
+        );
+        assert_eq!(header("for x in xs do end"), "x each xs");
+        assert_eq!(header("for x in [1, 2] do end"), "x each [1 2]");
+        assert_eq!(header("for step in steps do end"), "step each steps");
+        assert!(matches!(
+            parse("for i, 3 do end").unwrap().0[0].kind,
+            StmtKind::For { .. }
+        ));
+
+        assert_eq!(
+            error("for i in 1.. do end"),
+            "unexpected `do`: expected expression"
+        );
+        assert_eq!(
+            error("for i of xs do end"),
+            "unexpected `of`: expected one of `,`, `in`"
+        );
+        assert_eq!(
+            error("for i in 0..1 step do end"),
+            "unexpected `do`: expected expression"
+        );
+    }
+
+    #[test]
+    fn structs() {
+        assert_eq!(tree("p.x"), "(. p x)");
+        assert_eq!(tree("a[0].pos.x * 2"), "(* (. (. (index a 0) pos) x) 2)");
+        assert_eq!(tree("f(1).x"), "(. (f 1) x)");
+        assert_eq!(tree("{x: 1, y: p.y + 1}"), "{x: 1 y: (+ (. p y) 1)}");
+        assert_eq!(tree("Point(1, 2)"), "(Point 1 2)");
+        assert_eq!(parse("x\n.5").unwrap().0.len(), 2);
+
+        let program = parse("struct Point do x, y: float end\np.x = 1\n")
+            .unwrap()
+            .0;
+        match &program[0].kind {
+            StmtKind::Struct { name, fields } => assert_eq!(
+                (
+                    name.as_str(),
+                    fields
+                        .iter()
+                        .map(|f| (f.name.as_str(), f.ty.as_ref().map(|a| a.ty.clone())))
+                        .collect::<Vec<_>>()
+                ),
+                ("Point", vec![("x", None), ("y", Some(Type::Float))])
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+        match &program[1].kind {
+            StmtKind::FieldAssignment {
+                target,
+                field,
+                value,
+            } => assert_eq!(
+                (show(target), field.as_str(), show(value)),
+                (String::from("p"), "x", String::from("1"))
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+
+        let program = parse("f(p: Point) -> [Point] = [p]\n").unwrap().0;
+        match &program[0].kind {
+            StmtKind::FunctionDefinition { args, returns, .. } => assert_eq!(
+                (
+                    args[0].ty.as_ref().map(|a| a.ty.clone()),
+                    returns.as_ref().map(|a| a.ty.to_string())
+                ),
+                (
+                    Some(Type::Struct(String::from("Point"))),
+                    Some(String::from("[Point]"))
+                )
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+
+        assert_eq!(
+            error("struct P do end"),
+            "unexpected `end`: expected identifier"
+        );
+        assert_eq!(error("p. = 1"), "unexpected `=`: expected identifier");
+        assert_eq!(error("{x 1}"), "unexpected `1`: expected `:`");
+        assert_eq!(
+            error("local struct = 1"),
+            "unexpected `struct`: expected identifier"
+        );
+    }
+
+    #[test]
+    fn enums() {
+        let program = parse(
+            "enum Shape do Circle(r: float), Rect(w, h), Empty end
+",
+        )
+        .unwrap()
+        .0;
+        match &program[0].kind {
+            StmtKind::Enum { name, variants } => assert_eq!(
+                (
+                    name.as_str(),
+                    variants
+                        .iter()
+                        .map(|(name, fields)| (
+                            name.as_str(),
+                            fields.iter().map(|f| f.name.as_str()).collect::<Vec<_>>()
+                        ))
+                        .collect::<Vec<_>>()
+                ),
+                (
+                    "Shape",
+                    vec![
+                        ("Circle", vec!["r"]),
+                        ("Rect", vec!["w", "h"]),
+                        ("Empty", vec![])
+                    ]
+                )
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+
+        assert_eq!(
+            error("enum Shape do end"),
+            "unexpected `end`: expected identifier"
+        );
+        assert_eq!(
+            error("enum Shape do Circle() end"),
+            "unexpected `)`: expected identifier"
+        );
+        assert_eq!(
+            error("local match = 1"),
+            "unexpected `match`: expected identifier"
+        );
+    }
+
+    #[test]
+    fn matching() {
+        assert_eq!(
+            tree("match s do Circle(r) => r, Rect(w, _) if w > 0 => w\n _ => 0 end"),
+            "(match s ((Circle r) => r) ((Rect w _) if (> w 0) => w) (_ => 0))"
+        );
+        assert_eq!(
+            tree("match n do -1 => a, 0 => b, _x => c end + 1"),
+            "(+ (match n (-1 => a) (0 => b) (_x => c)) 1)"
+        );
+        assert_eq!(
+            tree("match x == y do true => 1, false => 2 end"),
+            "(match (== x y) (true => 1) (false => 2))"
+        );
+
+        let program = parse(
+            "match s do\n  Circle(r) => print(r)\n  Empty do\n    x = 1\n    y = 2\n  end\nend\n",
+        )
+        .unwrap()
+        .0;
+        match &program[0].kind {
+            StmtKind::Match { value, arms } => assert_eq!(
+                (
+                    show(value),
+                    arms.iter()
+                        .map(|arm| (show_pattern(&arm.pattern), arm.body.len()))
+                        .collect::<Vec<_>>()
+                ),
+                (
+                    String::from("s"),
+                    vec![(String::from("(Circle r)"), 1), (String::from("Empty"), 2)]
+                )
+            ),
+            kind => panic!("parsed as {:?}", kind),
+        }
+
+        assert_eq!(
+            error("match s do Circle(r) end"),
+            "unexpected `end`: expected one of `=>`, `do`"
+        );
+        assert_eq!(
+            error("match s do 1 + 2 => x end"),
+            "unexpected `+`: expected one of `=>`, `do`"
+        );
+        assert_eq!(
+            error("local x = match s do Circle() => 1 end"),
+            "unexpected `)`: expected a pattern"
+        );
     }
 }
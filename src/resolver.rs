@@ -0,0 +1,1291 @@
+use crate::diagnostics::{line_col, Diagnostic};
+use crate::types::*;
+use std::collections::{HashMap, HashSet};
+
+// Name resolution and closure analysis.
+//
+// Functions defined at the top level of the program are global: they are
+// visible everywhere and only see their own parameters. A function defined
+// anywhere else is local to the block it is defined in (including before its
+// definition and inside other functions nested there) and captures the
+// variables of the enclosing scopes by reference, so it sees their current
+// values and assignments to them are visible outside. A local function can
+// only be called once every variable it captures has been declared.
+//
+// Functions are also values: naming one without calling it, or writing a
+// lambda (`fn(x) => x * x`, an anonymous local function), gives a value that
+// can be stored, passed around and called through whatever holds it. A
+// function value can outlive the variables it captures, so it copies them when
+// it is created instead of sharing them, and assignments made through it only
+// change its own copies. A call goes to the nearest declaration of the name,
+// whether that is a function or a variable holding one.
+//
+// `array`, `len` and `push` are builtins, used by calls to them when no
+// function or variable of the same name is in scope. `push` has no value, so
+// it can only be called as a statement.
+//
+// Structs are declared at the top level and, like global functions, are
+// visible everywhere. Calling one by name, `Point(1, 2)`, makes one from its
+// fields in order, unless a function or variable of the same name is in scope.
+// A record, `{x: 1, y: 2}`, makes the struct that has exactly its fields. The
+// names of structs written in type annotations must be declared.
+//
+// Enums are declared at the top level too, and their variants are made the
+// same way as structs, `Circle(1.5)`, or just by name for a variant without
+// fields, `Empty`, when no variable of that name is in scope. Structs, enums
+// and variants all need different names. Each arm of a `match` is a scope of
+// its own, holding the names its pattern binds, which its guard can see too.
+// A name in a pattern binds whatever it matches, unless it is the name of a
+// variant or a struct.
+//
+// Every declaration is renamed to a name that is unique in the program (see
+// `unique_name`), and references are rewritten to match, so the backends can
+// treat names as identities. The variables each function needs from its
+// enclosing functions are written to its `captures`.
+
+struct Variable {
+    unique: String,
+    owner: usize,
+}
+
+struct Function {
+    unique: String,
+    arity: usize,
+    span: Span,
+}
+
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<String, Variable>,
+    functions: HashMap<String, Function>,
+    // `local`s further down the block, to explain uses that come before them
+    pending: HashMap<String, Span>,
+}
+
+enum Binding {
+    Variable,
+    Function {
+        unique: String,
+        arity: usize,
+        span: Span,
+    },
+}
+
+struct Call {
+    callee: String,
+    span: Span,
+    // the caller's own variables that were in scope at the call
+    in_scope: HashSet<String>,
+}
+
+// a function body, a lambda, or the top level of the program
+#[derive(Default)]
+struct Frame {
+    owned: HashSet<String>,
+    references: HashSet<String>,
+    calls: Vec<Call>,
+    captures: HashSet<String>,
+}
+
+pub struct Resolver<'a> {
+    source: &'a str,
+    scopes: Vec<Scope>,
+    // variables in scopes below this are not visible, used for global functions
+    barrier: usize,
+    frames: Vec<Frame>,
+    frame_of: HashMap<String, usize>,
+    current: usize,
+    // loops around the current statement, inside the current function
+    loops: usize,
+    // unique name -> (name in the source, declaration, declaration order)
+    variables: HashMap<String, (String, Span, usize)>,
+    variable_counts: HashMap<String, usize>,
+    function_counts: HashMap<String, usize>,
+    // struct name -> (field names, declaration)
+    structs: HashMap<String, (Vec<String>, Span)>,
+    // enum name -> declaration
+    enums: HashMap<String, Span>,
+    // variant name -> (enum, field names, declaration)
+    variants: HashMap<String, (String, Vec<String>, Span)>,
+    // struct, enum and variant name -> (which of those it is, declaration)
+    names: HashMap<String, (&'static str, Span)>,
+    errors: Vec<Diagnostic>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn resolve(prog: &mut Program, source: &'a str) -> Result<(), Vec<Diagnostic>> {
+        let mut resolver = Resolver {
+            source,
+            scopes: Vec::new(),
+            barrier: 0,
+            frames: vec![Frame::default()],
+            frame_of: HashMap::new(),
+            current: 0,
+            loops: 0,
+            variables: HashMap::new(),
+            variable_counts: HashMap::new(),
+            function_counts: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            variants: HashMap::new(),
+            names: HashMap::new(),
+            errors: Vec::new(),
+        };
+
+        resolver.declare_structs(prog);
+        resolver.check_annotations(prog);
+        resolver.resolve_block(prog, Scope::default());
+        resolver.compute_captures();
+        resolver.check_calls();
+        resolver.write_captures(prog);
+
+        if resolver.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(resolver.errors)
+        }
+    }
+
+    fn location(&self, span: Span) -> String {
+        let (line, col) = line_col(self.source, span.start);
+        format!("line {}, column {}", line, col)
+    }
+
+    fn declare_structs(&mut self, prog: &Program) {
+        for stmt in prog {
+            match &stmt.kind {
+                StmtKind::Struct { name, fields } => {
+                    if !self.claim("struct", name, stmt.span, prog) {
+                        continue;
+                    }
+                    let names = self.field_names("struct", name, fields, stmt.span);
+                    self.structs.insert(name.clone(), (names, stmt.span));
+                }
+                StmtKind::Enum { name, variants } => {
+                    if !self.claim("enum", name, stmt.span, prog) {
+                        continue;
+                    }
+                    self.enums.insert(name.clone(), stmt.span);
+                    for (variant, fields) in variants {
+                        if self.claim("variant", variant, stmt.span, prog) {
+                            let names = self.field_names("variant", variant, fields, stmt.span);
+                            self.variants
+                                .insert(variant.clone(), (name.clone(), names, stmt.span));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // whether `name` is free for a struct, enum or variant, which can't share
+    // it with each other or with a global function
+    fn claim(&mut self, kind: &'static str, name: &str, span: Span, prog: &Program) -> bool {
+        let function = prog.iter().find(|other| {
+            matches!(&other.kind, StmtKind::FunctionDefinition { name: f, .. } if f == name)
+        });
+        if let Some(&(previous_kind, previous)) = self.names.get(name) {
+            let message = if previous_kind == kind {
+                format!("{} {} already exists", kind, name)
+            } else {
+                format!(
+                    "{} {} has the same name as {} {}",
+                    kind, name, previous_kind, name
+                )
+            };
+            let note = format!("previously declared at {}", self.location(previous));
+            self.errors
+                .push(Diagnostic::new(message, span).with_note(note));
+            return false;
+        } else if let Some(function) = function {
+            let note = format!(
+                "the function is defined at {}",
+                self.location(function.span)
+            );
+            self.errors.push(
+                Diagnostic::new(
+                    format!("{} {} has the same name as a function", kind, name),
+                    span,
+                )
+                .with_note(note),
+            );
+            return false;
+        }
+        self.names.insert(String::from(name), (kind, span));
+        true
+    }
+
+    fn field_names(&mut self, kind: &str, name: &str, fields: &[Param], span: Span) -> Vec<String> {
+        let mut names = Vec::new();
+        for field in fields {
+            if names.contains(&field.name) {
+                self.errors.push(Diagnostic::new(
+                    format!("{} {} has two fields named {}", kind, name, field.name),
+                    span,
+                ));
+            }
+            names.push(field.name.clone());
+        }
+        names
+    }
+
+    // the fields of the struct or variant `name`, with what it is and where
+    // it is declared
+    fn constructor(&self, name: &str) -> Option<(&'static str, &[String], Span)> {
+        if let Some((fields, span)) = self.structs.get(name) {
+            return Some(("struct", fields, *span));
+        }
+        let (_, fields, span) = self.variants.get(name)?;
+        Some(("variant", fields, *span))
+    }
+
+    // `given` values for the fields of the struct or variant `name`, which
+    // has to be as many as it has
+    fn check_arity(&mut self, name: &str, given: usize, span: Span) -> bool {
+        let Some((kind, fields, declared)) = self.constructor(name) else {
+            return false;
+        };
+        if fields.len() == given {
+            return true;
+        }
+        let diagnostic = Diagnostic::new(
+            format!(
+                "{} {} has {} field{} but {} {} given",
+                kind,
+                name,
+                fields.len(),
+                if fields.len() == 1 { "" } else { "s" },
+                given,
+                if given == 1 { "was" } else { "were" }
+            ),
+            span,
+        )
+        .with_note(format!(
+            "{} is declared at {}",
+            name,
+            self.location(declared)
+        ));
+        self.errors.push(diagnostic);
+        false
+    }
+
+    // the structs and enums named in the types written in the program must
+    // exist, and the parser takes every name for a struct, so enums are
+    // corrected here
+    fn check_annotations(&mut self, body: &mut Program) {
+        for stmt in body {
+            match &mut stmt.kind {
+                StmtKind::FunctionDefinition { args, returns, .. } => {
+                    self.check_signature(args, returns.as_mut())
+                }
+                StmtKind::Struct { fields, .. } => self.check_signature(fields, None),
+                StmtKind::Enum { variants, .. } => {
+                    for (_, fields) in variants {
+                        self.check_signature(fields, None);
+                    }
+                }
+                _ => {}
+            }
+            for expr in stmt.exprs_mut() {
+                self.check_lambdas(expr);
+            }
+            for block in stmt.blocks_mut() {
+                self.check_annotations(block);
+            }
+        }
+    }
+
+    fn check_lambdas(&mut self, expr: &mut Expr) {
+        if let ExprKind::Lambda {
+            args,
+            returns,
+            body,
+            ..
+        } = &mut expr.kind
+        {
+            self.check_signature(args, returns.as_mut());
+            self.check_annotations(body);
+        }
+        for child in expr.children_mut() {
+            self.check_lambdas(child);
+        }
+    }
+
+    fn check_signature(&mut self, params: &mut [Param], returns: Option<&mut Annotation>) {
+        let annotations = params.iter_mut().filter_map(|param| param.ty.as_mut());
+        for annotation in annotations.chain(returns) {
+            self.check_type(&mut annotation.ty, annotation.span);
+        }
+    }
+
+    // every struct or enum named in `ty`, written at `span`
+    fn check_type(&mut self, ty: &mut Type, span: Span) {
+        match ty {
+            Type::Array(element) => self.check_type(element, span),
+            Type::Function(params, returns) => {
+                for param in params {
+                    self.check_type(param, span);
+                }
+                self.check_type(returns, span);
+            }
+            Type::Struct(name) if self.enums.contains_key(name) => {
+                *ty = Type::Enum(std::mem::take(name));
+            }
+            Type::Struct(name) if !self.structs.contains_key(name) => {
+                let mut diagnostic = Diagnostic::new(format!("unknown type {}", name), span)
+                    .with_label(
+                        "types are `int`, `float`, `bool`, arrays, functions, structs and enums",
+                    );
+                if let Some((ty, _, _)) = self.variants.get(name) {
+                    diagnostic = diagnostic.with_note(format!(
+                        "{} is a variant of {}, which is its type",
+                        name, ty
+                    ));
+                }
+                self.errors.push(diagnostic);
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_block(&mut self, body: &mut Program, mut scope: Scope) {
+        for stmt in body.iter_mut() {
+            match &mut stmt.kind {
+                StmtKind::Declaration { name, .. } => {
+                    scope.pending.entry(name.clone()).or_insert(stmt.span);
+                }
+                // functions are visible throughout the block they are defined in
+                StmtKind::FunctionDefinition { name, args, .. } => {
+                    if let Some(previous) = scope.functions.get(name.as_str()) {
+                        let note =
+                            format!("previously defined at {}", self.location(previous.span));
+                        self.errors.push(
+                            Diagnostic::new(format!("function {} already exists", name), stmt.span)
+                                .with_note(note),
+                        );
+                        continue;
+                    }
+
+                    let unique = unique_name(&mut self.function_counts, name);
+                    scope.functions.insert(
+                        std::mem::replace(name, unique.clone()),
+                        Function {
+                            unique,
+                            arity: args.len(),
+                            span: stmt.span,
+                        },
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        self.scopes.push(scope);
+        for stmt in body.iter_mut() {
+            self.resolve_stmt(stmt);
+        }
+        self.scopes.pop();
+    }
+
+    // the body of a loop, in a scope of its own with its counter or element
+    fn resolve_loop(&mut self, ident: &mut String, body: &mut Program, span: Span) {
+        self.scopes.push(Scope::default());
+        self.declare(ident, span);
+        self.loops += 1;
+        self.resolve_block(body, Scope::default());
+        self.loops -= 1;
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &mut String, span: Span) {
+        let unique = unique_name(&mut self.variable_counts, name);
+        let order = self.variables.len();
+        self.variables
+            .insert(unique.clone(), (name.clone(), span, order));
+        self.frames[self.current].owned.insert(unique.clone());
+
+        let scope = self.scopes.last_mut().unwrap();
+        scope.pending.remove(name.as_str());
+
+        let previous = scope.variables.insert(
+            name.clone(),
+            Variable {
+                unique: unique.clone(),
+                owner: self.current,
+            },
+        );
+
+        if let Some(previous) = previous {
+            let note = format!(
+                "previously declared at {}",
+                self.location(self.variables[&previous.unique].1)
+            );
+            self.errors.push(
+                Diagnostic::new(
+                    format!("variable {} is already declared in this scope", name),
+                    span,
+                )
+                .with_note(note),
+            );
+        }
+
+        *name = unique;
+    }
+
+    fn lookup(&mut self, name: &mut String, span: Span) {
+        let found = self.scopes[self.barrier..]
+            .iter()
+            .rev()
+            .find_map(|s| s.variables.get(name.as_str()));
+
+        if let Some(variable) = found {
+            if variable.owner != self.current {
+                self.frames[self.current]
+                    .references
+                    .insert(variable.unique.clone());
+            }
+            *name = variable.unique.clone();
+            return;
+        }
+
+        let mut diagnostic = Diagnostic::new(format!("variable {} is not defined", name), span);
+        if let Some(later) = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|s| s.pending.get(name.as_str()))
+        {
+            diagnostic = diagnostic.with_note(format!(
+                "{} is declared with `local` at {}, after this use",
+                name,
+                self.location(*later)
+            ));
+        }
+        self.errors.push(diagnostic);
+    }
+
+    // the nearest declaration of `name`, variable or function
+    fn find(&self, name: &str) -> Option<Binding> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, s)| match s.variables.get(name) {
+                Some(_) if i >= self.barrier => Some(Binding::Variable),
+                _ => s.functions.get(name).map(|f| Binding::Function {
+                    unique: f.unique.clone(),
+                    arity: f.arity,
+                    span: f.span,
+                }),
+            })
+    }
+
+    // calling a function or making a value of it needs what it captures
+    fn record_call(&mut self, callee: &str, span: Span) {
+        let in_scope = self.scopes[self.barrier..]
+            .iter()
+            .flat_map(|s| s.variables.values())
+            .filter(|v| v.owner == self.current)
+            .map(|v| v.unique.clone())
+            .collect();
+        self.frames[self.current].calls.push(Call {
+            callee: String::from(callee),
+            span,
+            in_scope,
+        });
+    }
+
+    fn resolve_function(
+        &mut self,
+        name: &str,
+        args: &mut [Param],
+        body: &mut Program,
+        span: Span,
+        global: bool,
+    ) {
+        let frame = self.frames.len();
+        self.frames.push(Frame::default());
+        self.frame_of.insert(String::from(name), frame);
+
+        let outer = (self.current, self.barrier, self.loops);
+        self.current = frame;
+        self.loops = 0;
+        if global {
+            self.barrier = self.scopes.len();
+        }
+
+        self.scopes.push(Scope::default());
+        for arg in args.iter_mut() {
+            self.declare(&mut arg.name, span);
+        }
+        let params = self.scopes.pop().unwrap();
+        self.resolve_block(body, params);
+
+        (self.current, self.barrier, self.loops) = outer;
+
+        if let Some((at, reason)) = missing_value(body, span) {
+            let mut diagnostic = Diagnostic::new(
+                format!(
+                    "{} does not return a value on every path",
+                    describe_function(name)
+                ),
+                at,
+            )
+            .with_label(reason);
+            if at != span {
+                diagnostic =
+                    diagnostic.with_note(format!("the function starts at {}", self.location(span)));
+            }
+            self.errors.push(
+                diagnostic.with_note(
+                    "a function returns its last expression, or the value of a `return`",
+                ),
+            );
+        }
+    }
+
+    fn resolve_stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::FunctionDefinition {
+                name, args, body, ..
+            } => {
+                let global = self.scopes.len() == 1;
+                self.resolve_function(name, args, body, stmt.span, global);
+            }
+            StmtKind::IfStatement { cond, body, branch } => {
+                self.resolve_expr(cond);
+                self.resolve_block(body, Scope::default());
+                if let Some(b) = branch {
+                    self.resolve_block(b, Scope::default());
+                }
+            }
+            StmtKind::For { body, ident, exprs } => {
+                for e in exprs {
+                    self.resolve_expr(e);
+                }
+                self.resolve_loop(ident, body, stmt.span);
+            }
+            StmtKind::ForRange {
+                body,
+                ident,
+                start,
+                stop,
+                step,
+                ..
+            } => {
+                self.resolve_expr(start);
+                self.resolve_expr(stop);
+                if let Some(step) = step {
+                    self.resolve_expr(step);
+                }
+                self.resolve_loop(ident, body, stmt.span);
+            }
+            StmtKind::ForEach { body, ident, array } => {
+                self.resolve_expr(array);
+                self.resolve_loop(ident, body, stmt.span);
+            }
+            StmtKind::While { body, expr } => {
+                self.resolve_expr(expr);
+                self.loops += 1;
+                self.resolve_block(body, Scope::default());
+                self.loops -= 1;
+            }
+            StmtKind::Declaration { name, value } => {
+                self.resolve_expr(value);
+                self.declare(name, stmt.span);
+            }
+            StmtKind::Assignment { name, value } => {
+                self.resolve_expr(value);
+                self.lookup(name, stmt.span);
+            }
+            StmtKind::Expression(expr) => match &mut expr.kind {
+                ExprKind::Call { name, args }
+                    if name == "push" && args.len() == 2 && self.find(name).is_none() =>
+                {
+                    let mut args = std::mem::take(args).into_iter();
+                    stmt.kind = StmtKind::Push {
+                        array: Box::new(args.next().unwrap()),
+                        value: Box::new(args.next().unwrap()),
+                    };
+                    self.resolve_stmt(stmt);
+                }
+                _ => self.resolve_expr(expr),
+            },
+            StmtKind::Struct { .. } => {
+                if self.scopes.len() != 1 {
+                    self.errors.push(Diagnostic::new(
+                        "structs can only be declared at the top level",
+                        stmt.span,
+                    ));
+                }
+            }
+            StmtKind::Enum { .. } => {
+                if self.scopes.len() != 1 {
+                    self.errors.push(Diagnostic::new(
+                        "enums can only be declared at the top level",
+                        stmt.span,
+                    ));
+                }
+            }
+            StmtKind::Match { value, arms } => {
+                self.resolve_expr(value);
+                for arm in arms {
+                    self.scopes.push(Scope::default());
+                    self.resolve_pattern(&mut arm.pattern);
+                    if let Some(guard) = &mut arm.guard {
+                        self.resolve_expr(guard);
+                    }
+                    self.resolve_block(&mut arm.body, Scope::default());
+                    self.scopes.pop();
+                }
+            }
+            StmtKind::FieldAssignment { target, value, .. } => {
+                self.resolve_expr(value);
+                self.resolve_expr(target);
+                if !is_place(target) {
+                    self.errors.push(
+                        Diagnostic::new("this field can't be assigned to", target.span)
+                            .with_label("not a variable, an element of an array or a field of one"),
+                    );
+                }
+            }
+            StmtKind::IndexAssignment {
+                array,
+                index,
+                value,
+            } => {
+                self.resolve_expr(value);
+                self.resolve_expr(array);
+                self.resolve_expr(index);
+            }
+            StmtKind::Push { array, value } => {
+                self.resolve_expr(value);
+                self.resolve_expr(array);
+            }
+            StmtKind::Print(args) => {
+                for (arg, format) in args {
+                    match &mut arg.kind {
+                        ExprKind::Str(segments) => {
+                            let interpolated = segments
+                                .iter()
+                                .any(|segment| matches!(segment, Segment::Value(..)));
+                            if format.precision.is_some() || format.scientific {
+                                self.errors.push(
+                                    Diagnostic::new("strings can only be given a width", arg.span)
+                                        .with_label("precision and `e` are for numbers"),
+                                );
+                            } else if interpolated && format.width > 0 {
+                                self.errors.push(
+                                    Diagnostic::new(
+                                        "only strings without interpolations can be given a width",
+                                        arg.span,
+                                    )
+                                    .with_label("give the values inside it a width instead"),
+                                );
+                            }
+                            for segment in segments {
+                                if let Segment::Value(expr, _) = segment {
+                                    self.resolve_expr(expr);
+                                }
+                            }
+                        }
+                        _ => self.resolve_expr(arg),
+                    }
+                }
+            }
+            StmtKind::Return(expr) => {
+                self.resolve_expr(expr);
+                if self.current == 0 {
+                    self.errors
+                        .push(Diagnostic::new("`return` outside of a function", stmt.span));
+                }
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                if self.loops == 0 {
+                    let keyword = match stmt.kind {
+                        StmtKind::Break => "break",
+                        _ => "continue",
+                    };
+                    self.errors.push(Diagnostic::new(
+                        format!("`{}` outside of a loop", keyword),
+                        stmt.span,
+                    ));
+                }
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) {
+        match &mut expr.kind {
+            ExprKind::Int(_) | ExprKind::Float(_) | ExprKind::Bool(_) => {}
+            ExprKind::Array(elements) => {
+                for element in elements {
+                    self.resolve_expr(element);
+                }
+            }
+            ExprKind::NewArray { .. }
+            | ExprKind::Len(_)
+            | ExprKind::Struct { .. }
+            | ExprKind::Variant { .. } => {
+                unreachable!("the resolver makes these from calls")
+            }
+            ExprKind::Match { value, arms } => {
+                self.resolve_expr(value);
+                for arm in arms {
+                    self.scopes.push(Scope::default());
+                    self.resolve_pattern(&mut arm.pattern);
+                    if let Some(guard) = &mut arm.guard {
+                        self.resolve_expr(guard);
+                    }
+                    self.resolve_expr(&mut arm.body);
+                    self.scopes.pop();
+                }
+            }
+            ExprKind::Record(fields) => {
+                for (_, value) in fields.iter_mut() {
+                    self.resolve_expr(value);
+                }
+                if let Some(name) = self.record(fields, expr.span) {
+                    expr.kind = ExprKind::Struct {
+                        name,
+                        fields: std::mem::take(fields),
+                    };
+                }
+            }
+            ExprKind::Field { expr, .. } => self.resolve_expr(expr),
+            ExprKind::Str(_) => self.errors.push(
+                Diagnostic::new("strings can only be printed", expr.span)
+                    .with_label("not an argument to `print`"),
+            ),
+            ExprKind::Ident(name) => match self.find(name) {
+                Some(Binding::Function { unique, .. }) => {
+                    self.record_call(&unique, expr.span);
+                    expr.kind = ExprKind::Function(unique);
+                }
+                None if self.variants.contains_key(name.as_str()) => {
+                    let (ty, fields, _) = &self.variants[name.as_str()];
+                    if fields.is_empty() {
+                        expr.kind = ExprKind::Variant {
+                            ty: ty.clone(),
+                            name: std::mem::take(name),
+                            fields: vec![],
+                        };
+                    } else {
+                        self.errors.push(
+                            Diagnostic::new(format!("variant {} has fields", name), expr.span)
+                                .with_label(format!("write `{}(...)` to make one", name)),
+                        );
+                    }
+                }
+                _ => self.lookup(name, expr.span),
+            },
+            ExprKind::Function(_) => {}
+            ExprKind::Call { name, args } => {
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg);
+                }
+
+                match self.find(name) {
+                    Some(Binding::Variable) => {
+                        let span = Span::new(expr.span.start, expr.span.start + name.len());
+                        let mut callee = Expr {
+                            kind: ExprKind::Ident(std::mem::take(name)),
+                            span,
+                        };
+                        self.resolve_expr(&mut callee);
+                        expr.kind = ExprKind::CallValue {
+                            callee: Box::new(callee),
+                            args: std::mem::take(args),
+                        };
+                    }
+                    Some(Binding::Function { arity, span, .. }) if arity != args.len() => {
+                        let diagnostic = Diagnostic::new(
+                            format!(
+                                "function {} takes {} argument{} but {} {} given",
+                                name,
+                                arity,
+                                if arity == 1 { "" } else { "s" },
+                                args.len(),
+                                if args.len() == 1 { "was" } else { "were" }
+                            ),
+                            expr.span,
+                        )
+                        .with_note(format!(
+                            "{} is defined at {}",
+                            name,
+                            self.location(span)
+                        ));
+                        self.errors.push(diagnostic);
+                    }
+                    Some(Binding::Function { unique, .. }) => {
+                        self.record_call(&unique, expr.span);
+                        *name = unique;
+                    }
+                    None if self.constructor(name).is_some() => {
+                        if self.check_arity(name, args.len(), expr.span) {
+                            let (_, fields, _) = self.constructor(name).unwrap();
+                            let fields = fields.iter().cloned().zip(std::mem::take(args)).collect();
+                            let name = std::mem::take(name);
+                            expr.kind = match self.variants.get(&name) {
+                                Some((ty, _, _)) => ExprKind::Variant {
+                                    ty: ty.clone(),
+                                    name,
+                                    fields,
+                                },
+                                None => ExprKind::Struct { name, fields },
+                            };
+                        }
+                    }
+                    None => match (name.as_str(), args.len()) {
+                        ("array", 1 | 2) => {
+                            let mut args = std::mem::take(args).into_iter();
+                            expr.kind = ExprKind::NewArray {
+                                len: Box::new(args.next().unwrap()),
+                                fill: args.next().map(Box::new),
+                            };
+                        }
+                        ("len", 1) => {
+                            expr.kind = ExprKind::Len(Box::new(args.pop().unwrap()));
+                        }
+                        ("push", 2) => self.errors.push(
+                            Diagnostic::new("`push` has no value", expr.span)
+                                .with_label("it can only be used as a statement"),
+                        ),
+                        ("array" | "len" | "push", given) => {
+                            let takes = match name.as_str() {
+                                "array" => "1 or 2 arguments",
+                                "len" => "1 argument",
+                                _ => "2 arguments",
+                            };
+                            self.errors.push(Diagnostic::new(
+                                format!(
+                                    "{} takes {} but {} {} given",
+                                    name,
+                                    takes,
+                                    given,
+                                    if given == 1 { "was" } else { "were" }
+                                ),
+                                expr.span,
+                            ));
+                        }
+                        _ => self.errors.push(Diagnostic::new(
+                            format!("function {} is not defined", name),
+                            expr.span,
+                        )),
+                    },
+                }
+            }
+            ExprKind::CallValue { callee, args } => {
+                self.resolve_expr(callee);
+                for arg in args.iter_mut() {
+                    self.resolve_expr(arg);
+                }
+            }
+            ExprKind::Lambda {
+                name, args, body, ..
+            } => {
+                *name = unique_name(&mut self.function_counts, name);
+                self.resolve_function(name, args, body, expr.span, false);
+                self.record_call(name, expr.span);
+            }
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mul(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::Leq(lhs, rhs)
+            | ExprKind::Geq(lhs, rhs)
+            | ExprKind::Lt(lhs, rhs)
+            | ExprKind::Gt(lhs, rhs)
+            | ExprKind::Eq(lhs, rhs)
+            | ExprKind::Neq(lhs, rhs)
+            | ExprKind::And(lhs, rhs)
+            | ExprKind::Or(lhs, rhs)
+            | ExprKind::Xor(lhs, rhs)
+            | ExprKind::Index {
+                array: lhs,
+                index: rhs,
+            } => {
+                self.resolve_expr(lhs);
+                self.resolve_expr(rhs);
+            }
+            ExprKind::Neg(e) | ExprKind::Pos(e) | ExprKind::Not(e) => self.resolve_expr(e),
+        }
+    }
+
+    // declares the names a pattern binds, in the scope of its arm
+    fn resolve_pattern(&mut self, pattern: &mut Pattern) {
+        match &mut pattern.kind {
+            PatternKind::Wildcard | PatternKind::Int(_) | PatternKind::Bool(_) => {}
+            PatternKind::Binding(name) => {
+                if self.constructor(name).is_none() {
+                    self.declare(name, pattern.span);
+                } else if self.check_arity(name, 0, pattern.span) {
+                    pattern.kind = PatternKind::Constructor {
+                        name: std::mem::take(name),
+                        fields: vec![],
+                    };
+                }
+            }
+            PatternKind::Constructor { name, fields } => {
+                if self.constructor(name).is_none() {
+                    self.errors.push(
+                        Diagnostic::new(
+                            format!("no variant or struct is named {}", name),
+                            pattern.span,
+                        )
+                        .with_label("patterns can only take apart variants and structs"),
+                    );
+                } else {
+                    self.check_arity(name, fields.len(), pattern.span);
+                }
+                for field in fields {
+                    self.resolve_pattern(field);
+                }
+            }
+        }
+    }
+
+    // the struct with exactly the fields of a record
+    fn record(&mut self, fields: &[(String, Expr)], span: Span) -> Option<String> {
+        let mut names = Vec::new();
+        for (name, value) in fields {
+            if names.contains(&name) {
+                self.errors.push(
+                    Diagnostic::new(format!("field {} is given twice", name), value.span)
+                        .with_label("a struct has one of each field"),
+                );
+                return None;
+            }
+            names.push(name);
+        }
+
+        let mut matching = self
+            .structs
+            .iter()
+            .filter(|(_, (fields, _))| {
+                fields.len() == names.len() && fields.iter().all(|f| names.contains(&f))
+            })
+            .collect::<Vec<_>>();
+        matching.sort_by_key(|(_, (_, span))| span.start);
+
+        let listed = names
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match matching[..] {
+            [(name, _)] => Some(name.clone()),
+            [] => {
+                self.errors.push(Diagnostic::new(
+                    format!("no struct has the fields {}", listed),
+                    span,
+                ));
+                None
+            }
+            [(first, _), (second, _), ..] => {
+                self.errors.push(
+                    Diagnostic::new(
+                        format!("both {} and {} have the fields {}", first, second, listed),
+                        span,
+                    )
+                    .with_label(format!(
+                        "write `{}(...)` or `{}(...)` instead",
+                        first, second
+                    )),
+                );
+                None
+            }
+        }
+    }
+
+    // a function captures what it references from enclosing functions, plus
+    // whatever the functions it calls capture that it doesn't own itself
+    fn compute_captures(&mut self) {
+        for frame in self.frames.iter_mut() {
+            frame.captures = frame.references.clone();
+        }
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for f in 0..self.frames.len() {
+                let needed = self.frames[f]
+                    .calls
+                    .iter()
+                    .flat_map(|call| &self.frames[self.frame_of[&call.callee]].captures)
+                    .filter(|v| !self.frames[f].owned.contains(*v))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for v in needed {
+                    changed |= self.frames[f].captures.insert(v);
+                }
+            }
+        }
+    }
+
+    fn check_calls(&mut self) {
+        let mut errors = Vec::new();
+
+        for frame in &self.frames {
+            for call in &frame.calls {
+                let callee = &self.frames[self.frame_of[&call.callee]];
+                for v in self.ordered(&callee.captures) {
+                    if frame.owned.contains(&v) && !call.in_scope.contains(&v) {
+                        let (name, span, _) = &self.variables[&v];
+                        errors.push(
+                            Diagnostic::new(
+                                format!(
+                                    "{} captures {}, which is not declared yet here",
+                                    describe_function(&call.callee),
+                                    name
+                                ),
+                                call.span,
+                            )
+                            .with_note(format!(
+                                "{} is declared at {}",
+                                name,
+                                self.location(*span)
+                            )),
+                        );
+                    }
+                }
+            }
+        }
+
+        self.errors.extend(errors);
+    }
+
+    fn ordered(&self, variables: &HashSet<String>) -> Vec<String> {
+        let mut ordered = variables.iter().cloned().collect::<Vec<_>>();
+        ordered.sort_by_key(|v| self.variables[v].2);
+        ordered
+    }
+
+    fn write_captures(&self, body: &mut Program) {
+        for stmt in body.iter_mut() {
+            if let StmtKind::FunctionDefinition { name, captures, .. } = &mut stmt.kind {
+                if let Some(&frame) = self.frame_of.get(name.as_str()) {
+                    *captures = self.ordered(&self.frames[frame].captures);
+                }
+            }
+            for block in stmt.blocks_mut() {
+                self.write_captures(block);
+            }
+            for expr in stmt.exprs_mut() {
+                self.write_lambda_captures(expr);
+            }
+        }
+    }
+
+    fn write_lambda_captures(&self, expr: &mut Expr) {
+        if let ExprKind::Lambda {
+            name,
+            body,
+            captures,
+            ..
+        } = &mut expr.kind
+        {
+            *captures = self.ordered(&self.frames[self.frame_of[name.as_str()]].captures);
+            self.write_captures(body);
+        }
+        for child in expr.children_mut() {
+            self.write_lambda_captures(child);
+        }
+    }
+}
+
+// where a block in tail position can finish without a value, and why. The
+// last expression of a block is its value, and an `if` with an `else` or a
+// `match` at the end of one has the value of whichever branch runs.
+fn missing_value(body: &Program, span: Span) -> Option<(Span, &'static str)> {
+    if always_returns(body) {
+        return None;
+    }
+
+    let last = match body.last() {
+        Some(last) => last,
+        None => return Some((span, "this block is empty")),
+    };
+
+    match &last.kind {
+        StmtKind::Expression(_) => None,
+        StmtKind::IfStatement {
+            body,
+            branch: Some(branch),
+            ..
+        } => missing_value(body, last.span).or_else(|| missing_value(branch, last.span)),
+        StmtKind::IfStatement { branch: None, .. } => Some((
+            last.span,
+            "this `if` has no `else`, so there is no value when its condition is false",
+        )),
+        StmtKind::Match { arms, .. } => arms
+            .iter()
+            .find_map(|arm| missing_value(&arm.body, last.span)),
+        StmtKind::For { .. }
+        | StmtKind::ForRange { .. }
+        | StmtKind::ForEach { .. }
+        | StmtKind::While { .. } => Some((
+            last.span,
+            "a loop has no value, so the function needs a `return` after it",
+        )),
+        _ => Some((last.span, "this statement has no value")),
+    }
+}
+
+// loops might not run at all, so only `if`s with both branches count, and
+// `match`es, which always run one of their arms
+fn always_returns(body: &Program) -> bool {
+    body.iter().any(|stmt| match &stmt.kind {
+        StmtKind::Return(_) => true,
+        StmtKind::IfStatement {
+            body,
+            branch: Some(branch),
+            ..
+        } => always_returns(body) && always_returns(branch),
+        StmtKind::Match { arms, .. } => {
+            !arms.is_empty() && arms.iter().all(|arm| always_returns(&arm.body))
+        }
+        _ => false,
+    })
+}
+
+// the first declaration of a name keeps it, later ones become `name#1`,
+// `name#2`, ...
+fn unique_name(counts: &mut HashMap<String, usize>, name: &str) -> String {
+    let count = counts.entry(String::from(name)).or_insert(0);
+    let unique = match *count {
+        0 => String::from(name),
+        n => format!("{}#{}", name, n),
+    };
+    *count += 1;
+    unique
+}
+
+pub fn original_name(unique: &str) -> &str {
+    unique.split('#').next().unwrap()
+}
+
+// lambdas are all named `fn`
+pub fn describe_function(unique: &str) -> String {
+    match original_name(unique) {
+        "fn" => String::from("lambda"),
+        name => format!("function {}", name),
+    }
+}
+
+// what a field assignment can change: a variable, an element of an array
+// (which is shared, wherever the array came from), or a field of one
+fn is_place(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::Ident(_) | ExprKind::Index { .. } => true,
+        ExprKind::Field { expr, .. } => is_place(expr),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn resolve(source: &str) -> Result<Program, Vec<Diagnostic>> {
+        let (mut program, _) = parse(source).expect("parses");
+        Resolver::resolve(&mut program, source).map(|()| program)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        match resolve(source) {
+            Ok(_) => panic!("{} resolves", source),
+            Err(errors) => errors.into_iter().map(|e| e.message).collect(),
+        }
+    }
+
+    // what each function defined in `source` captures, by its name there
+    fn captures(source: &str) -> Vec<(String, Vec<String>)> {
+        fn collect(body: &Program, found: &mut Vec<(String, Vec<String>)>) {
+            for stmt in body {
+                if let StmtKind::FunctionDefinition { name, captures, .. } = &stmt.kind {
+                    let captures = captures.iter().map(|c| original_name(c).to_string());
+                    found.push((original_name(name).to_string(), captures.collect()));
+                }
+                for block in stmt.blocks() {
+                    collect(block, found);
+                }
+            }
+        }
+        let program = resolve(source).unwrap_or_else(|e| panic!("{:?}", e));
+        let mut found = Vec::new();
+        collect(&program, &mut found);
+        found
+    }
+
+    #[test]
+    fn scopes() {
+        assert!(resolve("local x = 1\nif x > 0 do\nlocal x = 2\nprint(x)\nend").is_ok());
+        assert_eq!(errors("print(y)"), ["variable y is not defined"]);
+        assert_eq!(
+            errors("if true do\nlocal y = 1\nend\nprint(y)"),
+            ["variable y is not defined"]
+        );
+        assert_eq!(
+            errors("local x = 1\nlocal x = 2"),
+            ["variable x is already declared in this scope"]
+        );
+        // top-level functions only see their parameters
+        assert_eq!(
+            errors("local k = 2\nf(x) = x * k"),
+            ["variable k is not defined"]
+        );
+        assert_eq!(errors("return 1"), ["`return` outside of a function"]);
+        assert_eq!(errors("break"), ["`break` outside of a loop"]);
+    }
+
+    #[test]
+    fn arity() {
+        assert!(resolve("f(a, b) = a + b\nf(1, 2)").is_ok());
+        assert_eq!(
+            errors("f(a, b) = a + b\nf(1)"),
+            ["function f takes 2 arguments but 1 was given"]
+        );
+        assert_eq!(
+            errors("struct P do x, y end\nP(1, 2, 3)"),
+            ["struct P has 2 fields but 3 were given"]
+        );
+        assert_eq!(
+            errors("f(x) = x\nf(x) = x + 1"),
+            ["function f already exists"]
+        );
+    }
+
+    #[test]
+    fn closures() {
+        assert_eq!(
+            captures("outer() do\nlocal n = 0\nlocal m = 1\nstep() = n + 1\nstep()\nend"),
+            [
+                (String::from("outer"), vec![]),
+                (String::from("step"), vec![String::from("n")])
+            ]
+        );
+        // what a nested function captures, the one around it captures too
+        assert_eq!(
+            captures("outer() do\nlocal n = 0\nmid() do\ninner() = n\ninner()\nend\nmid()\nend"),
+            [
+                (String::from("outer"), vec![]),
+                (String::from("mid"), vec![String::from("n")]),
+                (String::from("inner"), vec![String::from("n")])
+            ]
+        );
+        // local functions can be called before they are defined, and
+        // recursively
+        assert_eq!(
+            captures("outer() do\nlocal k = 2\nlocal r = f(3)\nf(n) do\nif n < 1 do\nreturn k\nend\nf(n - 1)\nend\nr\nend"),
+            [
+                (String::from("outer"), vec![]),
+                (String::from("f"), vec![String::from("k")])
+            ]
+        );
+    }
+}
@@ -1,29 +1,89 @@
-#[derive(Debug, Clone, PartialEq)]
-pub enum ExprToken {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Pow,
-    Mod,
-    Leq,
-    Geq,
-    Lt,
-    Gt,
-    Eq,
-    Neq,
-    LParen,
-    RParen,
-    Number(f64),
-    Ident(String),
-    Call {
-        name: String,
-        args: Vec<Vec<ExprToken>>,
-    },
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+// ints are 64 bit and wrap on overflow, floats are doubles, and arrays are
+// references to their elements, so copies of one share them. Structs and
+// enums are values, by the name of their declaration, so copies of one are
+// separate. Functions are typed by what they take and return.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Array(Box<Type>),
+    Struct(String),
+    Enum(String),
+    Function(Vec<Type>, Box<Type>),
+}
+
+impl Type {
+    // the type of arithmetic on both, where an int meeting a float becomes one
+    pub fn join(self, other: Type) -> Type {
+        match (self, other) {
+            (Type::Int, Type::Int) => Type::Int,
+            _ => Type::Float,
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    // whether a value of type `other` can be stored in a `self`, which an int
+    // can as a float. The elements of arrays have to match exactly, as they
+    // are shared rather than converted, and so do the types of functions,
+    // which are called as they are.
+    pub fn accepts(&self, other: &Type) -> bool {
+        self == other || (self, other) == (&Type::Float, &Type::Int)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Array(element) => write!(f, "[{}]", element),
+            Type::Struct(name) | Type::Enum(name) => write!(f, "{}", name),
+            Type::Function(params, returns) => {
+                let params = params.iter().map(Type::to_string).collect::<Vec<_>>();
+                write!(f, "fn({}) -> {}", params.join(", "), returns)
+            }
+        }
+    }
+}
+
+// a type written in the source, such as the `int` in `f(x: int)`
+#[derive(Debug, Clone)]
+pub struct Annotation {
+    pub ty: Type,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub ty: Option<Annotation>,
 }
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub enum ExprKind {
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
@@ -36,19 +96,278 @@ pub enum Expr {
     Gt(Box<Expr>, Box<Expr>),
     Eq(Box<Expr>, Box<Expr>),
     Neq(Box<Expr>, Box<Expr>),
-    Number(f64),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Xor(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    Pos(Box<Expr>),
+    Not(Box<Expr>),
+    // `3`
+    Int(i64),
+    // `3.0` or `.5`
+    Float(f64),
+    // `true` or `false`
+    Bool(bool),
+    // strings can only be printed
+    Str(Vec<Segment>),
+    // `[1, 2, 3]`
+    Array(Vec<Expr>),
+    // `array(n)` for `n` zeros, or `array(n, fill)`
+    NewArray {
+        len: Box<Expr>,
+        fill: Option<Box<Expr>>,
+    },
+    // `a[i]`
+    Index {
+        array: Box<Expr>,
+        index: Box<Expr>,
+    },
+    // `len(a)`
+    Len(Box<Expr>),
+    // `{x: 1, y: 2}`, until the resolver finds the struct it makes
+    Record(Vec<(String, Expr)>),
+    // `Point(1, 2)` or a record, with its fields in the order they are written
+    Struct {
+        name: String,
+        fields: Vec<(String, Expr)>,
+    },
+    // `p.x`
+    Field {
+        expr: Box<Expr>,
+        name: String,
+    },
+    // `Circle(1.5)` or `Empty`, a variant of the enum `ty`, which the
+    // resolver makes from calls and names
+    Variant {
+        ty: String,
+        name: String,
+        fields: Vec<(String, Expr)>,
+    },
+    // `match value do pattern => expr ... end`
+    Match {
+        value: Box<Expr>,
+        arms: Vec<Arm<Expr>>,
+    },
     Ident(String),
-    Call { name: String, args: Vec<Box<Expr>> },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    // a named function used as a value
+    Function(String),
+    // a call through a function value
+    CallValue {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Lambda {
+        // `fn` until the resolver makes it unique
+        name: String,
+        args: Vec<Param>,
+        returns: Option<Annotation>,
+        body: Program,
+        // filled in by the resolver
+        captures: Vec<String>,
+    },
+}
+
+// a piece of a string literal: text with its escapes already replaced, or a
+// `{expr:format}` interpolation
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Text(String),
+    Value(Expr, Format),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    // the expressions nested directly inside this one, not counting the
+    // bodies of lambdas
+    pub fn children(&self) -> Vec<&Expr> {
+        match &self.kind {
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mul(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::Leq(lhs, rhs)
+            | ExprKind::Geq(lhs, rhs)
+            | ExprKind::Lt(lhs, rhs)
+            | ExprKind::Gt(lhs, rhs)
+            | ExprKind::Eq(lhs, rhs)
+            | ExprKind::Neq(lhs, rhs)
+            | ExprKind::And(lhs, rhs)
+            | ExprKind::Or(lhs, rhs)
+            | ExprKind::Xor(lhs, rhs)
+            | ExprKind::Index {
+                array: lhs,
+                index: rhs,
+            } => vec![lhs, rhs],
+            ExprKind::Neg(e)
+            | ExprKind::Pos(e)
+            | ExprKind::Not(e)
+            | ExprKind::Len(e)
+            | ExprKind::Field { expr: e, .. } => vec![e],
+            ExprKind::Call { args, .. } | ExprKind::Array(args) => args.iter().collect(),
+            ExprKind::Record(fields)
+            | ExprKind::Struct { fields, .. }
+            | ExprKind::Variant { fields, .. } => fields.iter().map(|(_, value)| value).collect(),
+            ExprKind::Match { value, arms } => {
+                let mut children = vec![&**value];
+                for arm in arms {
+                    children.extend(&arm.guard);
+                    children.push(&arm.body);
+                }
+                children
+            }
+            ExprKind::NewArray { len, fill } => {
+                let mut children = vec![&**len];
+                children.extend(fill.as_deref());
+                children
+            }
+            ExprKind::Str(segments) => segments
+                .iter()
+                .filter_map(|segment| match segment {
+                    Segment::Value(expr, _) => Some(expr),
+                    Segment::Text(_) => None,
+                })
+                .collect(),
+            ExprKind::CallValue { callee, args } => {
+                let mut children = vec![&**callee];
+                children.extend(args);
+                children
+            }
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Ident(_)
+            | ExprKind::Function(_)
+            | ExprKind::Lambda { .. } => vec![],
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match &mut self.kind {
+            ExprKind::Add(lhs, rhs)
+            | ExprKind::Sub(lhs, rhs)
+            | ExprKind::Mul(lhs, rhs)
+            | ExprKind::Div(lhs, rhs)
+            | ExprKind::Pow(lhs, rhs)
+            | ExprKind::Mod(lhs, rhs)
+            | ExprKind::Leq(lhs, rhs)
+            | ExprKind::Geq(lhs, rhs)
+            | ExprKind::Lt(lhs, rhs)
+            | ExprKind::Gt(lhs, rhs)
+            | ExprKind::Eq(lhs, rhs)
+            | ExprKind::Neq(lhs, rhs)
+            | ExprKind::And(lhs, rhs)
+            | ExprKind::Or(lhs, rhs)
+            | ExprKind::Xor(lhs, rhs)
+            | ExprKind::Index {
+                array: lhs,
+                index: rhs,
+            } => vec![lhs, rhs],
+            ExprKind::Neg(e)
+            | ExprKind::Pos(e)
+            | ExprKind::Not(e)
+            | ExprKind::Len(e)
+            | ExprKind::Field { expr: e, .. } => vec![e],
+            ExprKind::Call { args, .. } | ExprKind::Array(args) => args.iter_mut().collect(),
+            ExprKind::Record(fields)
+            | ExprKind::Struct { fields, .. }
+            | ExprKind::Variant { fields, .. } => {
+                fields.iter_mut().map(|(_, value)| value).collect()
+            }
+            ExprKind::Match { value, arms } => {
+                let mut children = vec![&mut **value];
+                for arm in arms {
+                    children.extend(&mut arm.guard);
+                    children.push(&mut arm.body);
+                }
+                children
+            }
+            ExprKind::NewArray { len, fill } => {
+                let mut children = vec![&mut **len];
+                children.extend(fill.as_deref_mut());
+                children
+            }
+            ExprKind::Str(segments) => segments
+                .iter_mut()
+                .filter_map(|segment| match segment {
+                    Segment::Value(expr, _) => Some(expr),
+                    Segment::Text(_) => None,
+                })
+                .collect(),
+            ExprKind::CallValue { callee, args } => {
+                let mut children = vec![&mut **callee];
+                children.extend(args);
+                children
+            }
+            ExprKind::Int(_)
+            | ExprKind::Float(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Ident(_)
+            | ExprKind::Function(_)
+            | ExprKind::Lambda { .. } => vec![],
+        }
+    }
 }
 
 pub type Program = Vec<Stmt>;
 
-#[derive(Debug)]
-pub enum Stmt {
+// `pattern if guard => body` in a `match`, where the body is a block in a
+// statement and an expression in an expression
+#[derive(Debug, Clone)]
+pub struct Arm<T> {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    pub body: T,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum PatternKind {
+    // `_`
+    Wildcard,
+    // `x`, matching anything and naming it, unless the resolver finds it is
+    // a variant without fields
+    Binding(String),
+    Int(i64),
+    Bool(bool),
+    // `Circle(r)` or `Point(x, _)`, with a pattern for each field in order
+    Constructor { name: String, fields: Vec<Pattern> },
+}
+
+// how `print` writes a number: padded on the left to at least `width`
+// characters, with `precision` digits after the point, in scientific notation
+// if `scientific`. Without a precision integers print without a fraction.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Format {
+    pub width: usize,
+    pub precision: Option<usize>,
+    pub scientific: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
     FunctionDefinition {
         name: String,
-        args: Vec<String>,
+        args: Vec<Param>,
+        returns: Option<Annotation>,
         body: Program,
+        // filled in by the resolver
+        captures: Vec<String>,
     },
     IfStatement {
         cond: Box<Expr>,
@@ -58,7 +377,22 @@ pub enum Stmt {
     For {
         body: Program,
         ident: String,
-        exprs: Vec<Box<Expr>>,
+        exprs: Vec<Expr>,
+    },
+    // `for i in start..stop step s`, with `..=` to include `stop`
+    ForRange {
+        body: Program,
+        ident: String,
+        start: Box<Expr>,
+        stop: Box<Expr>,
+        inclusive: bool,
+        step: Option<Box<Expr>>,
+    },
+    // `for x in array`
+    ForEach {
+        body: Program,
+        ident: String,
+        array: Box<Expr>,
     },
     While {
         body: Program,
@@ -72,5 +406,189 @@ pub enum Stmt {
         name: String,
         value: Box<Expr>,
     },
+    // `struct Point do x, y end`, whose fields can have types like parameters
+    Struct {
+        name: String,
+        fields: Vec<Param>,
+    },
+    // `enum Shape do Circle(r), Rect(w, h), Empty end`, with the fields of
+    // each variant
+    Enum {
+        name: String,
+        variants: Vec<(String, Vec<Param>)>,
+    },
+    // `match value do pattern => stmt ... end`, or `pattern do ... end`
+    Match {
+        value: Box<Expr>,
+        arms: Vec<Arm<Program>>,
+    },
+    // `p.x = value`, where `p` is a variable, an element of an array or a
+    // field of either
+    FieldAssignment {
+        target: Box<Expr>,
+        field: String,
+        value: Box<Expr>,
+    },
+    // `a[i] = value`
+    IndexAssignment {
+        array: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    // `push(a, value)`, which adds an element to the end of `a`
+    Push {
+        array: Box<Expr>,
+        value: Box<Expr>,
+    },
     Expression(Box<Expr>),
+    // arguments are written separated by spaces, followed by a newline
+    Print(Vec<(Expr, Format)>),
+    Return(Box<Expr>),
+    Break,
+    Continue,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    // the blocks nested directly inside this statement
+    pub fn blocks(&self) -> Vec<&Program> {
+        match &self.kind {
+            StmtKind::FunctionDefinition { body, .. }
+            | StmtKind::For { body, .. }
+            | StmtKind::ForRange { body, .. }
+            | StmtKind::ForEach { body, .. }
+            | StmtKind::While { body, .. } => vec![body],
+            StmtKind::IfStatement { body, branch, .. } => {
+                let mut blocks = vec![body];
+                blocks.extend(branch);
+                blocks
+            }
+            StmtKind::Match { arms, .. } => arms.iter().map(|arm| &arm.body).collect(),
+            StmtKind::Declaration { .. }
+            | StmtKind::Assignment { .. }
+            | StmtKind::Struct { .. }
+            | StmtKind::Enum { .. }
+            | StmtKind::FieldAssignment { .. }
+            | StmtKind::IndexAssignment { .. }
+            | StmtKind::Push { .. }
+            | StmtKind::Expression(_)
+            | StmtKind::Print(_)
+            | StmtKind::Return(_)
+            | StmtKind::Break
+            | StmtKind::Continue => vec![],
+        }
+    }
+
+    pub fn blocks_mut(&mut self) -> Vec<&mut Program> {
+        match &mut self.kind {
+            StmtKind::FunctionDefinition { body, .. }
+            | StmtKind::For { body, .. }
+            | StmtKind::ForRange { body, .. }
+            | StmtKind::ForEach { body, .. }
+            | StmtKind::While { body, .. } => vec![body],
+            StmtKind::IfStatement { body, branch, .. } => {
+                let mut blocks = vec![body];
+                blocks.extend(branch);
+                blocks
+            }
+            StmtKind::Match { arms, .. } => arms.iter_mut().map(|arm| &mut arm.body).collect(),
+            StmtKind::Declaration { .. }
+            | StmtKind::Assignment { .. }
+            | StmtKind::Struct { .. }
+            | StmtKind::Enum { .. }
+            | StmtKind::FieldAssignment { .. }
+            | StmtKind::IndexAssignment { .. }
+            | StmtKind::Push { .. }
+            | StmtKind::Expression(_)
+            | StmtKind::Print(_)
+            | StmtKind::Return(_)
+            | StmtKind::Break
+            | StmtKind::Continue => vec![],
+        }
+    }
+
+    // the expressions directly inside this statement, not counting the ones in
+    // nested blocks
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match &self.kind {
+            StmtKind::FunctionDefinition { .. }
+            | StmtKind::Struct { .. }
+            | StmtKind::Enum { .. }
+            | StmtKind::Break
+            | StmtKind::Continue => vec![],
+            StmtKind::IfStatement { cond: expr, .. }
+            | StmtKind::While { expr, .. }
+            | StmtKind::Declaration { value: expr, .. }
+            | StmtKind::Assignment { value: expr, .. }
+            | StmtKind::ForEach { array: expr, .. }
+            | StmtKind::Expression(expr)
+            | StmtKind::Return(expr) => vec![expr],
+            StmtKind::For { exprs, .. } => exprs.iter().collect(),
+            StmtKind::ForRange {
+                start, stop, step, ..
+            } => {
+                let mut exprs = vec![&**start, &**stop];
+                exprs.extend(step.as_deref());
+                exprs
+            }
+            StmtKind::IndexAssignment {
+                array,
+                index,
+                value,
+            } => vec![array, index, value],
+            StmtKind::Push { array, value } => vec![array, value],
+            StmtKind::FieldAssignment { target, value, .. } => vec![target, value],
+            // the guards are inside the scope of their arm, with what its
+            // pattern names
+            StmtKind::Match { value, arms } => {
+                let mut exprs = vec![&**value];
+                exprs.extend(arms.iter().filter_map(|arm| arm.guard.as_ref()));
+                exprs
+            }
+            StmtKind::Print(args) => args.iter().map(|(expr, _)| expr).collect(),
+        }
+    }
+
+    pub fn exprs_mut(&mut self) -> Vec<&mut Expr> {
+        match &mut self.kind {
+            StmtKind::FunctionDefinition { .. }
+            | StmtKind::Struct { .. }
+            | StmtKind::Enum { .. }
+            | StmtKind::Break
+            | StmtKind::Continue => vec![],
+            StmtKind::IfStatement { cond: expr, .. }
+            | StmtKind::While { expr, .. }
+            | StmtKind::Declaration { value: expr, .. }
+            | StmtKind::Assignment { value: expr, .. }
+            | StmtKind::ForEach { array: expr, .. }
+            | StmtKind::Expression(expr)
+            | StmtKind::Return(expr) => vec![expr],
+            StmtKind::For { exprs, .. } => exprs.iter_mut().collect(),
+            StmtKind::ForRange {
+                start, stop, step, ..
+            } => {
+                let mut exprs = vec![&mut **start, &mut **stop];
+                exprs.extend(step.as_deref_mut());
+                exprs
+            }
+            StmtKind::IndexAssignment {
+                array,
+                index,
+                value,
+            } => vec![array, index, value],
+            StmtKind::Push { array, value } => vec![array, value],
+            StmtKind::FieldAssignment { target, value, .. } => vec![target, value],
+            StmtKind::Match { value, arms } => {
+                let mut exprs = vec![&mut **value];
+                exprs.extend(arms.iter_mut().filter_map(|arm| arm.guard.as_mut()));
+                exprs
+            }
+            StmtKind::Print(args) => args.iter_mut().map(|(expr, _)| expr).collect(),
+        }
+    }
 }
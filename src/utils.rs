@@ -1,14 +1,62 @@
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-
-pub fn generate_function_name(name: impl AsRef<str>) -> String {
-    format!(
-        "f{}_{}",
-        thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(15)
-            .map(char::from)
-            .collect::<String>(),
-        name.as_ref()
-    )
+use crate::resolver::original_name;
+use std::collections::HashSet;
+use std::iter::once;
+
+// every cx name gets a prefix in the generated C, so identifiers like `int`,
+// `printf` or `main` can never collide with C keywords or libc symbols
+
+// the resolver renames shadowing declarations to `name#n`, which becomes
+// `v<n>_name` so it can't collide with another `v_` name
+pub fn mangle_variable(name: impl AsRef<str>) -> String {
+    match name.as_ref().split_once('#') {
+        Some((name, n)) => format!("v{}_{}", n, name),
+        None => format!("v_{}", name.as_ref()),
+    }
+}
+
+#[derive(Default)]
+pub struct Mangler {
+    used: HashSet<String>,
+}
+
+impl Mangler {
+    // `f_<enclosing functions>_<name>`, with a counter appended if that is
+    // already taken, so the output only depends on the order of definitions
+    pub fn function(&mut self, path: &[String], name: impl AsRef<str>) -> String {
+        let mut base = String::from("f");
+        for segment in path.iter().map(String::as_str).chain(once(name.as_ref())) {
+            let segment = original_name(segment);
+            base.push('_');
+            base.push_str(segment);
+        }
+
+        let mut mangled = base.clone();
+        let mut counter = 0;
+        while !self.used.insert(mangled.clone()) {
+            counter += 1;
+            mangled = format!("{}_{}", base, counter);
+        }
+
+        mangled
+    }
+}
+
+// a C string literal with the same bytes as `s`
+pub fn c_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for b in s.bytes() {
+        match b {
+            b'"' | b'\\' => {
+                literal.push('\\');
+                literal.push(b as char);
+            }
+            b'\n' => literal.push_str("\\n"),
+            b'\t' => literal.push_str("\\t"),
+            // `?` could start a trigraph
+            b' '..=b'~' if b != b'?' => literal.push(b as char),
+            _ => literal.push_str(&format!("\\{:03o}", b)),
+        }
+    }
+    literal.push('"');
+    literal
 }